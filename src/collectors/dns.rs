@@ -1,68 +1,664 @@
-//! DNS collector makes a DNS query (A record) to a hostname and records the
-//! time to finish the query.
-use std::net::Ipv4Addr;
+//! DNS collector makes a DNS query to a hostname and records the time to finish
+//! the query and details of the response. Queries are sent over UDP, TCP, TLS
+//! or HTTPS depending on the server.
+//!
+//! Reported metrics are `latency`, which is the total time, `connect_time` and
+//! `query_time` when the transport sets up a connection, `rcode` (e.g. 0 for NOERROR and 3 for
+//! NXDOMAIN), `answer_count`, which is the number of answer records of the
+//! queried type so that CNAME records are not counted, and `ttl`, which is
+//! the lowest TTL of those records. When expected values are configured
+//! `answer_matched` tells whether the answer records had exactly those values.
+//!
+//! Servers can also be read from the system resolver configuration at each
+//! collection. The `dns_server_source` tag tells whether the server was
+//! configured (`config`) or read from `resolv.conf` or `systemd-resolved`.
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-use hickory_client::client::{Client, SyncClient};
-use hickory_client::rr::{DNSClass, Name, RecordType};
-use hickory_client::udp::UdpClientConnection;
+use hickory_client::op::{Message as DnsMessage, MessageType, OpCode, Query};
+use hickory_client::rr::{Name, Record, RecordType};
+use log::{debug, error};
+use url::Host as UrlHost;
 
-use super::Collector;
-use crate::config::Timeout;
-use crate::error::{Result, ResultError};
+use super::{as_millis, failure_message, Collector};
+use crate::config::{AddressFamily, DnsRecordType, DnsServer, Timeout};
+use crate::error::{Error, ErrorKind, Result, ResultError};
 use crate::message::Message;
+use crate::net::dns::{self, Exchange};
+use crate::net::{self, IpFamily};
 use crate::url::Host;
 
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+/// Upstream servers of systemd-resolved when `/etc/resolv.conf` points to
+/// its local stub resolver.
+const SYSTEMD_RESOLVED_CONF: &str = "/run/systemd/resolve/resolv.conf";
+const SYSTEMD_RESOLVED: &str = "systemd-resolved";
+/// Local stub resolver of systemd-resolved which only forwards queries to the
+/// upstream servers.
+const SYSTEMD_RESOLVED_STUB: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 53));
+
 pub struct Dns {
-    server: Ipv4Addr,
+    server: DnsServer,
+    server_source: &'static str,
+    family: Option<IpFamily>,
     host: Host,
+    record_type: DnsRecordType,
+    expected: Vec<String>,
     timeout: Duration,
 }
 
 impl Dns {
-    pub fn new(server: Ipv4Addr, host: Host, timeout: Timeout) -> Self {
+    pub fn new(
+        server: DnsServer,
+        family: Option<IpFamily>,
+        host: Host,
+        record_type: DnsRecordType,
+        expected: Vec<String>,
+        timeout: Timeout,
+    ) -> Self {
+        let mut expected: Vec<String> = expected
+            .iter()
+            .map(|value| normalize(value, record_type))
+            .collect();
+        expected.sort();
+        expected.dedup();
         Self {
             server,
+            server_source: "config",
+            family,
             host,
+            record_type,
+            expected,
             timeout: Duration::from_secs(timeout.into()),
         }
     }
 
-    fn make_dns_query(&self) -> Result<u128> {
-        let server_address = format!("{}:53", self.server).parse()?;
-        let conn = UdpClientConnection::with_timeout(server_address, self.timeout)?;
-        let client = SyncClient::new(conn);
-        let name = Name::from_str(&self.host.to_string())?;
+    /// Sets the value of `dns_server_source` tag.
+    pub fn server_source(mut self, source: &'static str) -> Self {
+        self.server_source = source;
+        self
+    }
+
+    /// Returns the name to query. IP addresses are converted to reverse
+    /// lookup names for PTR queries.
+    fn query_name(&self) -> Result<Name> {
+        match (&self.host, self.record_type) {
+            (Host::Host(UrlHost::Ipv4(addr)), DnsRecordType::Ptr) => {
+                Ok(Name::from(IpAddr::V4(*addr)))
+            }
+            (Host::Host(UrlHost::Ipv6(addr)), DnsRecordType::Ptr) => {
+                Ok(Name::from(IpAddr::V6(*addr)))
+            }
+            _ => Ok(Name::from_str(&self.host.to_string())?),
+        }
+    }
+
+    fn make_dns_query(&self) -> Result<(DnsMessage, Exchange)> {
+        let mut query = DnsMessage::new();
+        query
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(
+                self.query_name()?,
+                record_type(self.record_type),
+            ));
+        let query_bytes = query.to_vec()?;
+
+        let exchange = match &self.server {
+            DnsServer::Udp(addr) => dns::udp(*addr, &query_bytes, self.timeout)?,
+            DnsServer::Tcp(addr) => dns::tcp(*addr, &query_bytes, self.timeout)?,
+            DnsServer::Tls(server) => {
+                let addr = match server.addr {
+                    Some(addr) => addr,
+                    None => net::resolve(&server.name, self.family)?,
+                };
+                let addr = SocketAddr::new(addr, server.port);
+                dns::tls(addr, &server.name, &query_bytes, self.timeout)?
+            }
+            DnsServer::Https(url) => dns::https(url, self.family, &query_bytes, self.timeout)?,
+        };
+        let response = DnsMessage::from_vec(&exchange.response)?;
+        if response.id() != query.id() {
+            return Err(Error::new("DNS response ID doesn't match the query")
+                .set_kind(ErrorKind::DnsFailure)
+                .set_context(&self.server.to_string()));
+        }
+        Ok((response, exchange))
+    }
 
-        let now = Instant::now();
-        client.query(&name, DNSClass::IN, RecordType::A)?;
-        Ok(now.elapsed().as_millis())
+    /// Returns the answer records of the queried type without the CNAME
+    /// records which lead to them.
+    fn answers<'a>(&self, response: &'a DnsMessage) -> impl Iterator<Item = &'a Record> {
+        let record_type = record_type(self.record_type);
+        response
+            .answers()
+            .iter()
+            .filter(move |record| record.record_type() == record_type)
+    }
+
+    /// Returns the normalized values of the answer records of the queried type.
+    fn answer_values(&self, response: &DnsMessage) -> Vec<String> {
+        self.answers(response)
+            .filter_map(|record| record.data())
+            .map(|data| normalize(&data.to_string(), self.record_type))
+            .collect()
     }
 }
 
 impl Collector for Dns {
     fn collect(&self) -> Result<Vec<Message>> {
-        let latency = self.make_dns_query().set_source("dns_collector")?;
+        let (response, exchange) = self.make_dns_query().set_source("dns_collector")?;
+
+        let mut message = self.new_message();
+        let total = exchange.connect.unwrap_or_default() + exchange.query;
+        message.insert_metric("latency", as_millis(total));
+        if let Some(connect) = exchange.connect {
+            message.insert_metric("connect_time", as_millis(connect));
+            message.insert_metric("query_time", as_millis(exchange.query));
+        }
+        message.insert_metric("rcode", u16::from(response.response_code()));
+        message.insert_metric("answer_count", self.answers(&response).count() as u64);
+        if let Some(ttl) = self.answers(&response).map(|record| record.ttl()).min() {
+            message.insert_metric("ttl", ttl);
+        }
 
+        if !self.expected.is_empty() {
+            let mut values = self.answer_values(&response);
+            values.sort();
+            values.dedup();
+            message.insert_metric("answer_matched", u8::from(values == self.expected));
+        }
+        Ok(vec![message])
+    }
+
+    fn new_message(&self) -> Message {
         let mut message = Message::new("dns");
-        message.insert_metric("latency", latency);
         message.insert_tag("dns_server", &self.server.to_string());
+        message.insert_tag("dns_server_source", self.server_source);
+        message.insert_tag("transport", self.server.transport());
         message.insert_tag("host", &self.host.to_string());
-        Ok(vec![message])
+        message.insert_tag("record_type", self.record_type.as_str());
+        if let Some(family) = self.family {
+            message.insert_tag("address_family", &family.to_string());
+        }
+        message
+    }
+}
+
+/// Queries the nameservers of the system resolver configuration. The
+/// configuration is read at each collection so that changes in DHCP assigned
+/// servers are followed.
+pub struct SystemDns {
+    host: Host,
+    record_type: DnsRecordType,
+    expected: Vec<String>,
+    address_family: AddressFamily,
+    timeout: Timeout,
+    sources: Vec<(&'static str, PathBuf)>,
+}
+
+impl SystemDns {
+    pub fn new(
+        host: Host,
+        record_type: DnsRecordType,
+        expected: Vec<String>,
+        address_family: AddressFamily,
+        timeout: Timeout,
+    ) -> Self {
+        Self {
+            host,
+            record_type,
+            expected,
+            address_family,
+            timeout,
+            sources: vec![
+                ("resolv.conf", PathBuf::from(RESOLV_CONF)),
+                (SYSTEMD_RESOLVED, PathBuf::from(SYSTEMD_RESOLVED_CONF)),
+            ],
+        }
+    }
+
+    /// Returns the nameservers and their sources. Sources which don't exist
+    /// are skipped and servers which are listed in many sources are returned
+    /// once. The stub resolver of systemd-resolved is skipped when its
+    /// upstream servers are known.
+    fn read_servers(&self) -> Result<Vec<(&'static str, IpAddr)>> {
+        let mut servers: Vec<(&'static str, IpAddr)> = Vec::new();
+        let mut systemd_resolved = false;
+        for (source, path) in self.sources.iter() {
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(Error::new("Failed to read resolver configuration")
+                        .set_cause(err)
+                        .set_context(&path.display().to_string()))
+                }
+            };
+            systemd_resolved |= *source == SYSTEMD_RESOLVED;
+            for server in parse_resolv_conf(&contents) {
+                if !servers.iter().any(|(_, known)| *known == server) {
+                    servers.push((source, server));
+                }
+            }
+        }
+        if systemd_resolved {
+            servers.retain(|(_, server)| *server != SYSTEMD_RESOLVED_STUB);
+        }
+        Ok(servers)
     }
 }
 
+impl Collector for SystemDns {
+    fn collect(&self) -> Result<Vec<Message>> {
+        let servers = self.read_servers().set_source("dns_collector")?;
+        let servers: Vec<_> = servers
+            .into_iter()
+            .filter(|(_, server)| self.address_family.includes(server))
+            .collect();
+        if servers.is_empty() {
+            return Err(
+                Error::new("No nameservers in resolver configuration").set_source("dns_collector")
+            );
+        }
+
+        let mut messages = Vec::new();
+        for (source, server) in servers {
+            let family = match self.address_family {
+                AddressFamily::Any => None,
+                _ => Some(IpFamily::of(&server)),
+            };
+            let dns = Dns::new(
+                DnsServer::Udp(SocketAddr::new(server, 53)),
+                family,
+                self.host.clone(),
+                self.record_type,
+                self.expected.clone(),
+                self.timeout,
+            )
+            .server_source(source);
+            match dns.collect() {
+                Ok(collected) => messages.extend(collected),
+                Err(err) => {
+                    error!("{}", err);
+                    messages.push(failure_message(&dns, &err));
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    fn new_message(&self) -> Message {
+        let mut message = Message::new("dns");
+        message.insert_tag("host", &self.host.to_string());
+        message.insert_tag("record_type", self.record_type.as_str());
+        message.insert_tag("dns_server_source", "system");
+        message
+    }
+}
+
+/// Parses nameserver addresses from resolv.conf contents. Link-local IPv6
+/// addresses with a zone are skipped.
+fn parse_resolv_conf(contents: &str) -> Vec<IpAddr> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some("nameserver"), Some(addr)) => match addr.parse() {
+                    Ok(addr) => Some(addr),
+                    Err(_) => {
+                        debug!("Skipping nameserver {} in resolver configuration", addr);
+                        None
+                    }
+                },
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn record_type(record_type: DnsRecordType) -> RecordType {
+    match record_type {
+        DnsRecordType::A => RecordType::A,
+        DnsRecordType::Aaaa => RecordType::AAAA,
+        DnsRecordType::Mx => RecordType::MX,
+        DnsRecordType::Txt => RecordType::TXT,
+        DnsRecordType::Cname => RecordType::CNAME,
+        DnsRecordType::Ns => RecordType::NS,
+        DnsRecordType::Soa => RecordType::SOA,
+        DnsRecordType::Ptr => RecordType::PTR,
+        DnsRecordType::Srv => RecordType::SRV,
+    }
+}
+
+/// Normalizes a record value for comparison. Domain names are compared case
+/// insensitively and without the trailing dot. TXT records are compared as is.
+fn normalize(value: &str, record_type: DnsRecordType) -> String {
+    if record_type == DnsRecordType::Txt {
+        return value.to_string();
+    }
+    value
+        .split_whitespace()
+        .map(|part| part.trim_end_matches('.').to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 #[cfg(test)]
 mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, UdpSocket};
+    use std::thread;
+
+    use hickory_client::op::{Message as DnsMessage, MessageType, ResponseCode};
+    use hickory_client::rr::rdata::{A, CNAME, MX};
+    use hickory_client::rr::RData;
+    use hickory_client::serialize::binary::{BinDecodable, BinEncodable};
+    use rstest::*;
+
     use super::*;
+    use crate::message::PayloadValue;
+    use crate::test_util::TempDir;
+
+    /// Encodes a response to a query with the given records.
+    fn respond(query: &[u8], response_code: ResponseCode, answers: &[(RData, u32)]) -> Vec<u8> {
+        let query = DnsMessage::from_bytes(query).unwrap();
+        let name = query.queries()[0].name().clone();
+        let mut response = DnsMessage::new();
+        response
+            .set_id(query.id())
+            .set_message_type(MessageType::Response)
+            .set_response_code(response_code)
+            .add_queries(query.queries().to_vec());
+        for (data, ttl) in answers {
+            response.add_answer(Record::from_rdata(name.clone(), *ttl, data.clone()));
+        }
+        response.to_bytes().unwrap()
+    }
+
+    /// Starts a DNS server which answers one query with the given records.
+    fn start_server(response_code: ResponseCode, answers: Vec<(RData, u32)>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, peer) = socket.recv_from(&mut buf).unwrap();
+            let response = respond(&buf[..len], response_code, &answers);
+            socket.send_to(&response, peer).unwrap();
+        });
+        addr
+    }
+
+    /// Starts a DNS server which answers one query over TCP.
+    fn start_tcp_server(answers: Vec<(RData, u32)>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut query = vec![0; u16::from_be_bytes(len).into()];
+            stream.read_exact(&mut query).unwrap();
+            let response = respond(&query, ResponseCode::NoError, &answers);
+            stream
+                .write_all(&(response.len() as u16).to_be_bytes())
+                .unwrap();
+            stream.write_all(&response).unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn collect_a_records() {
+        let server = start_server(
+            ResponseCode::NoError,
+            vec![
+                (
+                    RData::CNAME(CNAME(Name::from_str("www.example.com.").unwrap())),
+                    30,
+                ),
+                (RData::A(A::new(192, 0, 2, 1)), 300),
+                (RData::A(A::new(192, 0, 2, 2)), 60),
+            ],
+        );
+        let dns = Dns::new(
+            DnsServer::Udp(server),
+            None,
+            "example.com".parse().unwrap(),
+            DnsRecordType::A,
+            vec!["192.0.2.2".to_string(), "192.0.2.1".to_string()],
+            Timeout(1),
+        );
+        let msg = dns.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.source(), "dns");
+        assert_eq!(msg.tags()["dns_server"], server.to_string());
+        assert_eq!(msg.tags()["host"], "example.com");
+        assert_eq!(msg.tags()["record_type"], "A");
+        assert_eq!(msg.tags()["transport"], "udp");
+        assert_eq!(msg.tags()["dns_server_source"], "config");
+        assert!(msg.metrics().get("latency").is_some());
+        assert!(msg.metrics().get("connect_time").is_none());
+        assert_eq!(msg.metrics()["rcode"], PayloadValue::Uint16(0));
+        assert_eq!(msg.metrics()["answer_count"], PayloadValue::Uint64(2));
+        assert_eq!(msg.metrics()["ttl"], PayloadValue::Uint32(60));
+        assert_eq!(msg.metrics()["answer_matched"], PayloadValue::Uint8(1));
+    }
+
+    #[rstest]
+    #[case(vec!["10 mail.example.com."], 1)]
+    #[case(vec!["10 MAIL.example.com"], 1)]
+    #[case(vec!["20 mail.example.com"], 0)]
+    #[case(vec!["10 mail.example.com", "20 backup.example.com"], 0)]
+    fn collect_mx_records_with_expected_values(#[case] expected: Vec<&str>, #[case] matched: u8) {
+        let mx = MX::new(10, Name::from_str("mail.example.com.").unwrap());
+        let server = start_server(ResponseCode::NoError, vec![(RData::MX(mx), 300)]);
+        let dns = Dns::new(
+            DnsServer::Udp(server),
+            None,
+            "example.com".parse().unwrap(),
+            DnsRecordType::Mx,
+            expected.into_iter().map(String::from).collect(),
+            Timeout(1),
+        );
+        let msg = dns.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.tags()["record_type"], "MX");
+        assert_eq!(
+            msg.metrics()["answer_matched"],
+            PayloadValue::Uint8(matched)
+        );
+    }
+
+    #[test]
+    fn collect_over_tcp() {
+        let server = start_tcp_server(vec![(RData::A(A::new(192, 0, 2, 1)), 300)]);
+        let dns = Dns::new(
+            DnsServer::Tcp(server),
+            None,
+            "example.com".parse().unwrap(),
+            DnsRecordType::A,
+            Vec::new(),
+            Timeout(1),
+        );
+        let msg = dns.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.tags()["dns_server"], format!("tcp://{}", server));
+        assert_eq!(msg.tags()["transport"], "tcp");
+        assert!(msg.metrics().get("connect_time").is_some());
+        assert!(msg.metrics().get("query_time").is_some());
+        assert_eq!(msg.metrics()["answer_count"], PayloadValue::Uint64(1));
+    }
+
+    #[test]
+    fn collect_nxdomain() {
+        let server = start_server(ResponseCode::NXDomain, Vec::new());
+        let dns = Dns::new(
+            DnsServer::Udp(server),
+            None,
+            "missing.example.com".parse().unwrap(),
+            DnsRecordType::Aaaa,
+            Vec::new(),
+            Timeout(1),
+        );
+        let msg = dns.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.metrics()["rcode"], PayloadValue::Uint16(3));
+        assert_eq!(msg.metrics()["answer_count"], PayloadValue::Uint64(0));
+        assert!(msg.metrics().get("ttl").is_none());
+        assert!(msg.metrics().get("answer_matched").is_none());
+    }
+
+    #[test]
+    fn ptr_query_name_from_ip_address() {
+        let dns = Dns::new(
+            "127.0.0.1".parse().unwrap(),
+            None,
+            "192.0.2.1".parse().unwrap(),
+            DnsRecordType::Ptr,
+            Vec::new(),
+            Timeout(1),
+        );
+
+        assert_eq!(
+            dns.query_name().unwrap().to_string(),
+            "1.2.0.192.in-addr.arpa."
+        );
+    }
+
+    #[rstest]
+    #[case("tls://192.0.2.53", "192.0.2.53", Some("192.0.2.53"), 853)]
+    #[case("tls://dns.example.com", "dns.example.com", None, 853)]
+    #[case("tls://dns.example.com:8853", "dns.example.com", None, 8853)]
+    #[case(
+        "tls://dns.example.com@192.0.2.53",
+        "dns.example.com",
+        Some("192.0.2.53"),
+        853
+    )]
+    #[case(
+        "tls://dns.example.com@[2001:db8::53]:8853",
+        "dns.example.com",
+        Some("2001:db8::53"),
+        8853
+    )]
+    fn parse_tls_server(
+        #[case] input: &str,
+        #[case] name: &str,
+        #[case] addr: Option<&str>,
+        #[case] port: u16,
+    ) {
+        let DnsServer::Tls(server) = input.parse().unwrap() else {
+            panic!("not a TLS server");
+        };
+
+        assert_eq!(server.name.to_string(), name);
+        assert_eq!(server.addr, addr.map(|addr| addr.parse().unwrap()));
+        assert_eq!(server.port, port);
+        assert_eq!(DnsServer::Tls(server).to_string(), input);
+    }
+
+    #[test]
+    fn parse_nameservers() {
+        let contents = "# Generated by NetworkManager\n\
+                        search example.com\n\
+                        nameserver 192.0.2.53\n\
+                        nameserver 2001:db8::53 # comment\n\
+                        nameserver fe80::1%eth0\n\
+                        options edns0\n";
+
+        assert_eq!(
+            parse_resolv_conf(contents),
+            vec![
+                "192.0.2.53".parse::<IpAddr>().unwrap(),
+                "2001:db8::53".parse().unwrap()
+            ]
+        );
+    }
+
+    fn system_dns(dir: &TempDir, sources: &[(&'static str, &str)]) -> SystemDns {
+        let mut dns = SystemDns::new(
+            "example.com".parse().unwrap(),
+            DnsRecordType::A,
+            Vec::new(),
+            AddressFamily::Any,
+            Timeout(1),
+        );
+        dns.sources = sources
+            .iter()
+            .enumerate()
+            .map(|(index, (source, contents))| {
+                let path = dir.join(index.to_string());
+                std::fs::write(&path, contents).unwrap();
+                (*source, path)
+            })
+            .collect();
+        dns.sources.push(("missing", dir.join("missing")));
+        dns
+    }
+
+    #[test]
+    fn read_system_dns_servers() {
+        let dir = TempDir::new("resolv");
+        let dns = system_dns(
+            &dir,
+            &[
+                (
+                    "resolv.conf",
+                    "nameserver 127.0.0.53\nnameserver 192.0.2.1\n",
+                ),
+                (
+                    "systemd-resolved",
+                    "nameserver 192.0.2.1\nnameserver 192.0.2.2\n",
+                ),
+            ],
+        );
+
+        assert_eq!(
+            dns.read_servers().unwrap(),
+            vec![
+                ("resolv.conf", "192.0.2.1".parse().unwrap()),
+                ("systemd-resolved", "192.0.2.2".parse().unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_stub_resolver_without_systemd_resolved() {
+        let dir = TempDir::new("stub");
+        let dns = system_dns(&dir, &[("resolv.conf", "nameserver 127.0.0.53\n")]);
+
+        assert_eq!(
+            dns.read_servers().unwrap(),
+            vec![("resolv.conf", "127.0.0.53".parse().unwrap())]
+        );
+    }
+
+    #[test]
+    fn collect_without_system_dns_servers() {
+        let dir = TempDir::new("empty");
+        let dns = system_dns(&dir, &[("resolv.conf", "search example.com\n")]);
+        let err = dns.collect().unwrap_err();
+
+        assert_eq!(err.source().as_ref().unwrap(), "dns_collector");
+        assert_eq!(dns.new_message().tags()["dns_server_source"], "system");
+    }
 
     #[test]
     #[ignore]
     fn dns_collect() {
         let dns = Dns::new(
             "8.8.8.8".parse().unwrap(),
+            None,
             "www.google.com".parse().unwrap(),
+            DnsRecordType::A,
+            Vec::new(),
             Timeout(1),
         );
         let msg = dns.collect().unwrap().pop().unwrap();
@@ -1,52 +1,196 @@
-//! HTTP collector sends a HEAD request to a defined URL and records the latency
-//! and status code of the returned response.
-use std::time::{Duration, Instant};
+//! HTTP collector sends a request to a defined URL and records the status code
+//! of the returned response and how long each phase of the request took.
+//! Requests are HEAD requests unless another method is configured.
+//!
+//! Phase durations are reported in milliseconds:
+//! - `dns_time`: resolving the host name
+//! - `connect_time`: TCP handshake
+//! - `tls_time`: TLS handshake, only for HTTPS URLs
+//! - `ttfb`: from sending the request to receiving the first response byte
+//! - `total_time`: the whole request
+//!
+//! HTTPS requests also report the TLS session and the server certificate:
+//! `cert_valid`, `cert_days_until_expiry`, `cert_issuer` and `cert_subject`
+//! metrics and `tls_version` and `tls_cipher` tags. Requests to servers with
+//! invalid certificates fail unless verification is skipped. The failure is
+//! reported with the certificate metrics so that e.g. an expired certificate
+//! shows up as negative `cert_days_until_expiry`.
+//!
+//! When response assertions are configured `assertion_passed` tells whether
+//! all of them passed and the `failed_assertion` tag lists the ones which
+//! failed.
+use std::time::Duration;
 
-use reqwest::blocking::{Client, Response};
+use chrono::Utc;
+use log::error;
+use regex::Regex;
 
-use super::Collector;
-use crate::config::Timeout;
+use super::{as_millis, failure_message, Collector};
+use crate::config::{HttpAuth, HttpOptions, Timeout};
 use crate::error::{Result, ResultError};
 use crate::message::Message;
+use crate::net::http::{Request, Response};
+use crate::net::tls::{CertificateError, CertificateInfo, TlsOptions};
+use crate::net::IpFamily;
 use crate::url::HttpUrl;
 
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
 pub struct Http {
     url: HttpUrl,
+    family: Option<IpFamily>,
+    options: HttpOptions,
+    body_regex: Option<Regex>,
     timeout: Duration,
 }
 
 impl Http {
-    pub fn new(url: HttpUrl, timeout: Timeout) -> Http {
+    pub fn new(
+        url: HttpUrl,
+        family: Option<IpFamily>,
+        options: HttpOptions,
+        timeout: Timeout,
+    ) -> Http {
+        let body_regex = options
+            .body_regex
+            .as_ref()
+            .map(|regex| Regex::new(regex).expect("Regex is validated in configuration"));
         Http {
             url,
+            family,
+            options,
+            body_regex,
             timeout: Duration::from_secs(timeout.into()),
         }
     }
 
     fn send_request(&self) -> Result<Response> {
-        let client = Client::builder()
-            .timeout(self.timeout)
-            .danger_accept_invalid_certs(true)
-            .build()?;
+        let mut request = Request::new(self.options.method.as_str(), &self.url, self.timeout);
+        for (name, value) in self.options.headers.iter() {
+            request = request.header(name, value);
+        }
+        match self.options.auth.as_ref() {
+            Some(HttpAuth::Basic { username, password }) => {
+                request = request.basic_auth(username, password.as_ref());
+            }
+            Some(HttpAuth::Bearer { token }) => request = request.bearer_auth(token.as_ref()),
+            None => (),
+        }
+        if let Some(body) = self.options.body.as_ref() {
+            request = request.body(body.as_bytes());
+        }
+        // Only the body assertions need the response body
+        if self.options.body_contains.is_none() && self.body_regex.is_none() {
+            request = request.discard_body();
+        }
+        request
+            .tls(TlsOptions {
+                skip_verify: self.options.tls_skip_verify,
+                ca_file: self.options.tls_ca_file.as_deref(),
+                ..Default::default()
+            })
+            .ip_family(self.family)
+            .send()
+    }
 
-        let resp = client.head(self.url.as_str()).send()?;
-        Ok(resp)
+    /// Returns the names of the assertions which the response did not pass.
+    fn failed_assertions(&self, resp: &Response) -> Vec<&'static str> {
+        let mut failed = Vec::new();
+        if !self.options.expected_status.is_empty()
+            && !self.options.expected_status.contains(&resp.status)
+        {
+            failed.push("status");
+        }
+        if let Some(max_response_time) = self.options.max_response_time {
+            if resp.timings.total > Duration::from_millis(max_response_time) {
+                failed.push("response_time");
+            }
+        }
+        let body = String::from_utf8_lossy(&resp.body);
+        if let Some(substring) = self.options.body_contains.as_ref() {
+            if !body.contains(substring.as_str()) {
+                failed.push("body_contains");
+            }
+        }
+        if let Some(regex) = self.body_regex.as_ref() {
+            if !regex.is_match(&body) {
+                failed.push("body_regex");
+            }
+        }
+        failed
     }
 }
 
 impl Collector for Http {
     fn collect(&self) -> Result<Vec<Message>> {
-        let now = Instant::now();
-        let resp = self.send_request().set_source("http_collector")?;
-        let latency = now.elapsed().as_millis();
+        let resp = match self.send_request().set_source("http_collector") {
+            Ok(resp) => resp,
+            Err(err) => {
+                let Some(cert_err) = err
+                    .cause()
+                    .as_ref()
+                    .and_then(|cause| cause.downcast_ref::<CertificateError>())
+                else {
+                    return Err(err);
+                };
+                error!("{}", err);
+                let mut message = failure_message(self, &err);
+                message.insert_metric("cert_valid", 0u8);
+                insert_certificate(&mut message, &cert_err.certificate);
+                return Ok(vec![message]);
+            }
+        };
+        let timings = resp.timings;
 
-        let mut message = Message::new("http");
-        message.insert_metric("latency", latency);
-        message.insert_metric("status_code", resp.status().as_u16());
-        message.insert_tag("url", self.url.as_str());
+        let mut message = self.new_message();
+        message.insert_tag("remote_ip", &resp.remote_addr.ip().to_string());
+        message.insert_metric("latency", timings.total.as_millis());
+        message.insert_metric("status_code", resp.status);
+        message.insert_metric("dns_time", as_millis(timings.dns));
+        message.insert_metric("connect_time", as_millis(timings.connect));
+        if let Some(tls) = timings.tls {
+            message.insert_metric("tls_time", as_millis(tls));
+        }
+        message.insert_metric("ttfb", as_millis(timings.ttfb));
+        message.insert_metric("total_time", as_millis(timings.total));
+
+        if let Some(tls) = resp.tls.as_ref() {
+            message.insert_tag("tls_version", &tls.version);
+            message.insert_tag("tls_cipher", &tls.cipher);
+            message.insert_metric("cert_valid", u8::from(tls.cert_valid));
+            if let Some(cert) = tls.certificate.as_ref() {
+                insert_certificate(&mut message, cert);
+            }
+        }
+
+        if self.options.has_assertions() {
+            let failed = self.failed_assertions(&resp);
+            message.insert_metric("assertion_passed", u8::from(failed.is_empty()));
+            if !failed.is_empty() {
+                message.insert_tag("failed_assertion", &failed.join(","));
+            }
+        }
 
         Ok(vec![message])
     }
+
+    fn new_message(&self) -> Message {
+        let mut message = Message::new("http");
+        message.insert_tag("url", self.url.as_str());
+        if let Some(family) = self.family {
+            message.insert_tag("address_family", &family.to_string());
+        }
+        message
+    }
+}
+
+fn insert_certificate(message: &mut Message, cert: &CertificateInfo) {
+    message.insert_metric(
+        "cert_days_until_expiry",
+        (cert.not_after - Utc::now().timestamp()).div_euclid(SECONDS_PER_DAY),
+    );
+    message.insert_metric("cert_issuer", cert.issuer.as_str());
+    message.insert_metric("cert_subject", cert.subject.as_str());
 }
 
 #[cfg(test)]
@@ -54,30 +198,178 @@ mod tests {
     extern crate mockito;
 
     use super::*;
+    use crate::config::HttpMethod;
+    use crate::error::ErrorKind;
     use crate::message::PayloadValue;
+    use crate::net::tls::tests::start_server;
+
     #[test]
     fn collect_successful() {
         let mut server = mockito::Server::new();
         let m = server.mock("HEAD", "/").with_status(201).create();
         let url: HttpUrl = server.url().parse().unwrap();
-        let http = Http::new(url.clone(), Timeout(1));
+        let http = Http::new(url.clone(), None, HttpOptions::default(), Timeout(1));
         let msg = http.collect().unwrap().pop().unwrap();
 
         assert_eq!(msg.source(), "http");
         assert_eq!(msg.metrics()["status_code"], PayloadValue::Uint16(201));
         assert!(msg.metrics().get("latency").is_some());
+        for metric in ["dns_time", "connect_time", "ttfb", "total_time"] {
+            assert!(msg.metrics().get(metric).is_some(), "{} missing", metric);
+        }
+        assert!(msg.metrics().get("tls_time").is_none());
         assert_eq!(msg.tags()["url"], url.to_string());
+        assert_eq!(
+            msg.tags()["remote_ip"],
+            server.socket_address().ip().to_string()
+        );
+        m.assert();
+    }
+
+    #[test]
+    fn collect_with_passed_assertions() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("GET", "/health")
+            .match_header("authorization", "Bearer secret")
+            .match_header("x-custom", "value")
+            .with_body("{\"status\": \"ok\"}")
+            .create();
+        let url: HttpUrl = format!("{}/health", server.url()).parse().unwrap();
+        let options = HttpOptions {
+            method: HttpMethod::Get,
+            headers: [("X-Custom".to_string(), "value".to_string())].into(),
+            auth: Some(HttpAuth::Bearer {
+                token: "secret".into(),
+            }),
+            expected_status: vec![200, 204],
+            body_contains: Some("status".to_string()),
+            body_regex: Some(r#""status":\s*"ok""#.to_string()),
+            max_response_time: Some(1000),
+            ..Default::default()
+        };
+        let msg = Http::new(url, None, options, Timeout(1))
+            .collect()
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        assert_eq!(msg.metrics()["assertion_passed"], PayloadValue::Uint8(1));
+        assert!(msg.tags().get("failed_assertion").is_none());
         m.assert();
     }
 
+    #[test]
+    fn collect_with_failed_assertions() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("POST", "/")
+            .match_header("authorization", "Basic dXNlcjpwYXNz")
+            .match_body("request")
+            .with_status(500)
+            .with_body("internal error")
+            .create();
+        let url: HttpUrl = server.url().parse().unwrap();
+        let options = HttpOptions {
+            method: HttpMethod::Post,
+            body: Some("request".to_string()),
+            auth: Some(HttpAuth::Basic {
+                username: "user".to_string(),
+                password: "pass".into(),
+            }),
+            expected_status: vec![200],
+            body_contains: Some("error".to_string()),
+            body_regex: Some("^ok$".to_string()),
+            ..Default::default()
+        };
+        let msg = Http::new(url, None, options, Timeout(1))
+            .collect()
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        assert_eq!(msg.metrics()["status_code"], PayloadValue::Uint16(500));
+        assert_eq!(msg.metrics()["assertion_passed"], PayloadValue::Uint8(0));
+        assert_eq!(msg.tags()["failed_assertion"], "status,body_regex");
+        m.assert();
+    }
+
+    #[test]
+    fn collect_tls_details() {
+        let server = start_server();
+        let url: HttpUrl = format!("https://{}/", server.addr).parse().unwrap();
+        let options = HttpOptions {
+            tls_skip_verify: true,
+            ..Default::default()
+        };
+        let msg = Http::new(url, None, options, Timeout(1))
+            .collect()
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        assert_eq!(msg.metrics()["cert_valid"], PayloadValue::Uint8(0));
+        assert!(msg.metrics().get("cert_days_until_expiry").is_some());
+        assert_eq!(
+            msg.metrics()["cert_subject"],
+            PayloadValue::String("CN=uption.test".to_string())
+        );
+        assert!(msg.tags()["tls_version"].starts_with("TLSv1."));
+        assert!(msg.metrics().get("tls_time").is_some());
+    }
+
+    #[test]
+    fn collect_untrusted_certificate_fails() {
+        let server = start_server();
+        let url: HttpUrl = format!("https://{}/", server.addr).parse().unwrap();
+        let msg = Http::new(url, None, HttpOptions::default(), Timeout(1))
+            .collect()
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        assert_eq!(msg.metrics()["success"], PayloadValue::Uint8(0));
+        assert_eq!(msg.tags()["error_class"], ErrorKind::Tls.to_string());
+        assert_eq!(msg.metrics()["cert_valid"], PayloadValue::Uint8(0));
+        assert!(msg.metrics().get("cert_days_until_expiry").is_some());
+        assert_eq!(
+            msg.metrics()["cert_issuer"],
+            PayloadValue::String("CN=Uption Test CA".to_string())
+        );
+    }
+
+    #[test]
+    fn collect_with_address_family() {
+        let mut server = mockito::Server::new();
+        let m = server.mock("HEAD", "/").create();
+        let url: HttpUrl = server.url().parse().unwrap();
+        let http = Http::new(
+            url.clone(),
+            Some(IpFamily::V4),
+            HttpOptions::default(),
+            Timeout(1),
+        );
+        let msg = http.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.tags()["address_family"], "ipv4");
+        m.assert();
+
+        let http = Http::new(url, Some(IpFamily::V6), HttpOptions::default(), Timeout(1));
+        let err = http.collect().unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::DnsFailure);
+        assert_eq!(http.new_message().tags()["address_family"], "ipv6");
+    }
+
     #[test]
     fn collect_failed() {
         let url: HttpUrl = "http://localhost:12345".parse().unwrap();
-        let http = Http::new(url, Timeout(1));
+        let http = Http::new(url, None, HttpOptions::default(), Timeout(1));
         let err = http.collect().unwrap_err();
 
         assert_eq!(err.source().as_ref().unwrap(), "http_collector");
         assert!(err.context().is_some());
         assert!(err.cause().is_none());
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
     }
 }
@@ -2,73 +2,266 @@
 //! collectors. Collectors gather different metrics which are sent to exporter.
 mod dns;
 mod http;
+mod netdev;
+mod netstat;
+mod ntp;
 mod ping;
+mod system;
+mod tcp;
+mod throughput;
+mod traceroute;
 mod wireless;
 
-use std::{thread, time::Duration};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crossbeam_channel::Sender;
-use log::{error, info};
+use log::{error, info, warn};
 
-use crate::config::{Configure, UptionConfig};
-use crate::error::Result;
-use crate::message::Message;
-pub use dns::Dns;
+use crate::config::{AddressFamily, Configure, UptionConfig};
+use crate::error::{Error, Result};
+use crate::message::{Message, PayloadValue};
+use crate::net::IpFamily;
+pub use dns::{Dns, SystemDns};
 pub use http::Http;
+pub use netdev::Netdev;
+pub use netstat::Netstat;
+pub use ntp::{ClockGuard, Ntp};
 pub use ping::Ping;
+pub use system::System;
+pub use tcp::Tcp;
+pub use throughput::{Direction, Throughput};
+pub use traceroute::Traceroute;
 pub use wireless::Wireless;
 
 /// Schedules the execution of different collectors. Collectors are not executed
 /// in parallel by design so that they would not interfere with each other.
+///
+/// Every collector runs at a fixed rate defined by its own interval. When
+/// several collectors are due at the same time they are executed one after
+/// another in the order they were registered.
 pub struct CollectorScheduler {
-    collectors: Vec<Box<dyn Collector + Send>>,
+    collectors: Vec<ScheduledCollector>,
+    clock_guard: Option<ClockGuard>,
+}
+
+struct ScheduledCollector {
+    collector: Box<dyn Collector + Send>,
     interval: Duration,
+    next_run: Instant,
+}
+
+impl ScheduledCollector {
+    /// Schedules the next run after a run which finished at `now`.
+    fn reschedule(&mut self, now: Instant) {
+        let next_run = next_deadline(self.next_run, self.interval, now);
+        if next_run > self.next_run + self.interval {
+            warn!(
+                "Collector {} is running behind its {:?} interval, skipping missed runs",
+                self.collector.new_message().source(),
+                self.interval
+            );
+        }
+        self.next_run = next_run;
+    }
 }
 
 impl CollectorScheduler {
-    pub fn new(interval: u64) -> CollectorScheduler {
+    pub fn new() -> CollectorScheduler {
         CollectorScheduler {
             collectors: Vec::new(),
-            interval: Duration::from_secs(interval),
+            clock_guard: None,
         }
     }
 
-    pub fn register(&mut self, collector: impl Collector + Send + 'static) {
-        self.collectors.push(Box::new(collector));
+    /// Sets the clock guard which checks every collected message.
+    pub fn set_clock_guard(&mut self, guard: ClockGuard) {
+        self.clock_guard = Some(guard);
+    }
+
+    pub fn register(&mut self, collector: impl Collector + Send + 'static, interval: Duration) {
+        self.collectors.push(ScheduledCollector {
+            collector: Box::new(collector),
+            interval,
+            next_run: Instant::now(),
+        });
     }
 
-    pub fn start(&self, sender: Sender<Message>, hostname: String) {
+    /// Starts collecting messages and sends every message to all of the
+    /// exporters.
+    pub fn start(&mut self, mut senders: Vec<Sender<Message>>, hostname: String) {
         if self.collectors.is_empty() {
             error!("No collectors configured!");
             return;
         }
         info!("Collector scheduler started");
 
+        let started = Instant::now();
+        for scheduled in self.collectors.iter_mut() {
+            scheduled.next_run = started;
+        }
+
         loop {
-            for collector in self.collectors.iter() {
-                let messages = match collector.collect() {
-                    Ok(msg) => msg,
-                    Err(err) => {
-                        error!("{}", err);
-                        continue;
+            let scheduled = next_due(&mut self.collectors);
+            let now = Instant::now();
+            if scheduled.next_run > now {
+                thread::sleep(scheduled.next_run - now);
+            }
+
+            let messages = collect(scheduled.collector.as_ref()).unwrap_or_else(|msg| vec![msg]);
+            let messages = messages
+                .into_iter()
+                .filter_map(|message| guard(self.clock_guard.as_ref(), message));
+            for mut message in messages {
+                message.insert_tag("hostname", &hostname);
+
+                senders.retain(|sender| match sender.send(message.clone()) {
+                    Ok(_) => true,
+                    Err(_) => {
+                        error!("Exporter disconnected");
+                        false
                     }
-                };
+                });
+                if senders.is_empty() {
+                    error!("All exporters disconnected. Stopping collectors.");
+                    return;
+                }
+            }
 
-                for mut message in messages {
-                    message.insert_tag("hostname", &hostname);
+            scheduled.reschedule(Instant::now());
+        }
+    }
 
-                    match sender.send(message) {
-                        Ok(_) => (),
-                        Err(_) => {
-                            error!("Exporter disconnected. Stopping collectors.");
-                            return;
-                        }
-                    };
+    /// Runs every collector once in the order they were registered. Returns
+    /// the collected messages and the number of failed collections.
+    pub fn run_once(&self, hostname: &str) -> (Vec<Message>, usize) {
+        let mut messages = Vec::new();
+        let mut failures = 0;
+        for scheduled in self.collectors.iter() {
+            match collect(scheduled.collector.as_ref()) {
+                Ok(collected) => {
+                    failures += collected.iter().filter(|msg| is_failure(msg)).count();
+                    messages.extend(collected);
+                }
+                Err(failure) => {
+                    failures += 1;
+                    messages.push(failure);
                 }
             }
-            thread::sleep(self.interval);
         }
+        let mut messages: Vec<_> = messages
+            .into_iter()
+            .filter_map(|message| guard(self.clock_guard.as_ref(), message))
+            .collect();
+        for message in messages.iter_mut() {
+            message.insert_tag("hostname", hostname);
+        }
+        (messages, failures)
+    }
+}
+
+impl Default for CollectorScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs a collector and returns the collected messages or a message which
+/// reports the failure.
+fn collect(collector: &(dyn Collector + Send)) -> std::result::Result<Vec<Message>, Message> {
+    match collector.collect() {
+        // Collectors which query many targets report failed targets with
+        // failure messages
+        Ok(messages) => Ok(messages
+            .into_iter()
+            .map(|mut message| {
+                if !message.metrics().contains_key("success") {
+                    message.insert_metric("success", 1u8);
+                }
+                message
+            })
+            .collect()),
+        Err(err) => {
+            error!("{}", err);
+            Err(failure_message(collector, &err))
+        }
+    }
+}
+
+/// Applies the clock guard to a collected message. Returns `None` if the
+/// message is dropped.
+fn guard(clock_guard: Option<&ClockGuard>, message: Message) -> Option<Message> {
+    match clock_guard {
+        Some(clock_guard) => clock_guard.apply(message),
+        None => Some(message),
+    }
+}
+
+fn is_failure(message: &Message) -> bool {
+    message.metrics().get("success") == Some(&PayloadValue::Uint8(0))
+}
+
+/// Returns the IP families which are measured separately for an address
+/// family preference.
+fn ip_families(family: AddressFamily) -> &'static [Option<IpFamily>] {
+    match family {
+        AddressFamily::Any => &[None],
+        AddressFamily::Ipv4 => &[Some(IpFamily::V4)],
+        AddressFamily::Ipv6 => &[Some(IpFamily::V6)],
+        AddressFamily::Both => &[Some(IpFamily::V4), Some(IpFamily::V6)],
+    }
+}
+
+/// Converts a duration to fractional milliseconds.
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Maximum value of kernel counters. They are `unsigned long` which is 32
+/// bits wide on 32-bit targets.
+const COUNTER_MAX: u64 = if cfg!(target_pointer_width = "32") {
+    u32::MAX as u64
+} else {
+    u64::MAX
+};
+
+/// Returns the increase of a kernel counter or `None` if it was reset.
+fn counter_delta(previous: u64, current: u64) -> Option<u64> {
+    wrapping_delta(previous, current, COUNTER_MAX)
+}
+
+/// Returns the increase of a counter which wraps around after `max`.
+/// Decreased counter which was in the upper half of the range is assumed to
+/// have wrapped around and other decreases are resets which have no increase.
+fn wrapping_delta(previous: u64, current: u64, max: u64) -> Option<u64> {
+    if current >= previous {
+        Some(current - previous)
+    } else if previous > max / 2 && previous <= max {
+        Some(max - previous + current + 1)
+    } else {
+        None
+    }
+}
+
+/// Returns the collector which is due next. Collectors which are due at the
+/// same time run in the order they were registered.
+fn next_due(collectors: &mut [ScheduledCollector]) -> &mut ScheduledCollector {
+    collectors
+        .iter_mut()
+        .min_by_key(|scheduled| scheduled.next_run)
+        .unwrap()
+}
+
+/// Returns the first deadline after `now` which is a whole number of
+/// intervals after the previous deadline. Deadlines don't drift by the time
+/// spent collecting and runs which were missed are skipped.
+fn next_deadline(previous: Instant, interval: Duration, now: Instant) -> Instant {
+    let next = previous + interval;
+    if next > now {
+        return next;
     }
+    let missed = (now - previous).as_nanos() / interval.as_nanos();
+    previous + interval.mul_f64((missed + 1) as f64)
 }
 
 /// All data collectors need to implement this trait. Collector scheduler uses
@@ -77,40 +270,403 @@ pub trait Collector {
     /// Starts data collection in the collector implementation and returns a
     /// message that will be sent to exporter.
     fn collect(&self) -> Result<Vec<Message>>;
+
+    /// Returns an empty message with the source and tags which identify the
+    /// collection target. Failed collections are reported with this message.
+    fn new_message(&self) -> Message;
+}
+
+/// Creates a message which reports a failed collection.
+fn failure_message(collector: &(dyn Collector + Send), err: &Error) -> Message {
+    let mut message = collector.new_message();
+    message.insert_metric("success", 0u8);
+    message.insert_metric("error", err.to_string());
+    message.insert_tag("error_class", &err.kind().to_string());
+    message
 }
 
 impl Configure for CollectorScheduler {
     fn from_config(config: &UptionConfig) -> Self {
-        let mut scheduler = CollectorScheduler::new(config.collectors.interval);
+        let mut scheduler = CollectorScheduler::new();
+        // Target interval overrides collector interval which overrides the
+        // default interval.
+        let interval = |target: Option<u64>, collector: Option<u64>| {
+            Duration::from_secs(target.or(collector).unwrap_or(config.collectors.interval))
+        };
+
+        // NTP collectors are registered first so that the clock is checked
+        // before the other collectors run.
+        let ntp_config = &config.collectors.ntp;
+        if ntp_config.enabled {
+            let longest_interval = ntp_config
+                .servers
+                .iter()
+                .map(|target| interval(target.interval, ntp_config.interval))
+                .max()
+                .unwrap_or_default();
+            let guard = ntp_config.max_offset.map(|max_offset| {
+                ClockGuard::new(max_offset, ntp_config.max_offset_action, longest_interval)
+            });
+            for target in ntp_config.servers.iter() {
+                for family in ip_families(ntp_config.address_family) {
+                    scheduler.register(
+                        Ntp::new(
+                            target.host.clone(),
+                            *family,
+                            ntp_config.timeout,
+                            guard.clone(),
+                        ),
+                        interval(target.interval, ntp_config.interval),
+                    );
+                }
+            }
+            if let Some(guard) = guard {
+                scheduler.set_clock_guard(guard);
+            }
+        }
 
         let ping_config = &config.collectors.ping;
         if ping_config.enabled {
-            for host in ping_config.hosts.iter() {
-                scheduler.register(Ping::new(host.clone(), ping_config.timeout));
+            for target in ping_config.hosts.iter() {
+                for family in ip_families(ping_config.address_family) {
+                    scheduler.register(
+                        Ping::new(
+                            target.host.clone(),
+                            *family,
+                            ping_config.timeout,
+                            ping_config.packet_count,
+                            ping_config.packet_interval,
+                        ),
+                        interval(target.interval, ping_config.interval),
+                    );
+                }
             }
         }
 
         let http_config = &config.collectors.http;
         if http_config.enabled {
-            for url in http_config.urls.iter() {
-                scheduler.register(Http::new(url.clone(), http_config.timeout));
+            for target in http_config.urls.iter() {
+                for family in ip_families(http_config.address_family) {
+                    scheduler.register(
+                        Http::new(
+                            target.url.clone(),
+                            *family,
+                            target.options.clone(),
+                            http_config.timeout,
+                        ),
+                        interval(target.interval, http_config.interval),
+                    );
+                }
             }
         }
 
         let dns_config = &config.collectors.dns;
         if dns_config.enabled {
             for server in dns_config.dns_servers.iter() {
-                for host in dns_config.hosts.iter() {
-                    scheduler.register(Dns::new(*server, host.clone(), dns_config.timeout));
+                // Servers are tagged with their family when a family
+                // preference is set. Servers which are defined with a domain
+                // name are queried over each preferred family.
+                let families = match (server.ip(), dns_config.address_family) {
+                    (Some(_), AddressFamily::Any) => vec![None],
+                    (Some(ip), family) if family.includes(&ip) => vec![Some(IpFamily::of(&ip))],
+                    (Some(_), _) => Vec::new(),
+                    (None, family) => ip_families(family).to_vec(),
+                };
+                for family in families {
+                    for target in dns_config.hosts.iter() {
+                        scheduler.register(
+                            Dns::new(
+                                server.clone(),
+                                family,
+                                target.host.clone(),
+                                target.options.record_type,
+                                target.options.expected.clone(),
+                                dns_config.timeout,
+                            ),
+                            interval(target.interval, dns_config.interval),
+                        );
+                    }
+                }
+            }
+        }
+
+        if dns_config.enabled && dns_config.system_dns_servers {
+            for target in dns_config.hosts.iter() {
+                scheduler.register(
+                    SystemDns::new(
+                        target.host.clone(),
+                        target.options.record_type,
+                        target.options.expected.clone(),
+                        dns_config.address_family,
+                        dns_config.timeout,
+                    ),
+                    interval(target.interval, dns_config.interval),
+                );
+            }
+        }
+
+        let tcp_config = &config.collectors.tcp;
+        if tcp_config.enabled {
+            for target in tcp_config.targets.iter() {
+                for family in ip_families(tcp_config.address_family) {
+                    scheduler.register(
+                        Tcp::new(
+                            target.address.clone(),
+                            *family,
+                            target.options.expect_banner.clone(),
+                            tcp_config.timeout,
+                        ),
+                        interval(target.interval, tcp_config.interval),
+                    );
+                }
+            }
+        }
+
+        let throughput_config = &config.collectors.throughput;
+        if throughput_config.enabled {
+            let targets = throughput_config
+                .downloads
+                .iter()
+                .map(|target| (Direction::Download, target))
+                .chain(
+                    throughput_config
+                        .uploads
+                        .iter()
+                        .map(|target| (Direction::Upload, target)),
+                );
+            for (direction, target) in targets {
+                scheduler.register(
+                    Throughput::new(
+                        target.url.clone(),
+                        direction,
+                        target.options.clone(),
+                        throughput_config.timeout,
+                    ),
+                    interval(target.interval, throughput_config.interval),
+                );
+            }
+        }
+
+        let netdev_config = &config.collectors.netdev;
+        if netdev_config.enabled {
+            scheduler.register(
+                Netdev::new(netdev_config.interfaces.clone()),
+                interval(None, netdev_config.interval),
+            );
+        }
+
+        let netstat_config = &config.collectors.netstat;
+        if netstat_config.enabled {
+            scheduler.register(Netstat::new(), interval(None, netstat_config.interval));
+        }
+
+        let system_config = &config.collectors.system;
+        if system_config.enabled {
+            scheduler.register(System::new(), interval(None, system_config.interval));
+        }
+
+        let traceroute_config = &config.collectors.traceroute;
+        if traceroute_config.enabled {
+            for target in traceroute_config.hosts.iter() {
+                for family in ip_families(traceroute_config.address_family) {
+                    scheduler.register(
+                        Traceroute::new(
+                            target.host.clone(),
+                            *family,
+                            traceroute_config.protocol,
+                            traceroute_config.max_hops,
+                            traceroute_config.probes,
+                            traceroute_config.probe_timeout,
+                            traceroute_config.timeout,
+                        ),
+                        interval(target.interval, traceroute_config.interval),
+                    );
                 }
             }
         }
 
         let wireless_config = &config.collectors.wireless;
         if wireless_config.enabled {
-            scheduler.register(Wireless::new());
+            scheduler.register(Wireless::new(), interval(None, wireless_config.interval));
         }
 
         scheduler
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::message::PayloadValue;
+
+    struct FailingCollector {}
+
+    impl Collector for FailingCollector {
+        fn collect(&self) -> Result<Vec<Message>> {
+            Err(Error::new("Collection failed").set_kind(ErrorKind::Timeout))
+        }
+
+        fn new_message(&self) -> Message {
+            let mut message = Message::new("failing");
+            message.insert_tag("host", "localhost");
+            message
+        }
+    }
+
+    struct NamedCollector {
+        name: &'static str,
+    }
+
+    impl Collector for NamedCollector {
+        fn collect(&self) -> Result<Vec<Message>> {
+            Ok(vec![self.new_message()])
+        }
+
+        fn new_message(&self) -> Message {
+            Message::new(self.name)
+        }
+    }
+
+    /// Collector which reports a failed target among successful ones.
+    struct PartiallyFailingCollector {}
+
+    impl Collector for PartiallyFailingCollector {
+        fn collect(&self) -> Result<Vec<Message>> {
+            let err = Error::new("Collection failed");
+            Ok(vec![
+                self.new_message(),
+                failure_message(&FailingCollector {}, &err),
+            ])
+        }
+
+        fn new_message(&self) -> Message {
+            Message::new("partial")
+        }
+    }
+
+    #[test]
+    fn failed_targets_are_counted_as_failures() {
+        let mut scheduler = CollectorScheduler::new();
+        scheduler.register(PartiallyFailingCollector {}, Duration::from_secs(1));
+        let (messages, failures) = scheduler.run_once("host");
+
+        assert_eq!(failures, 1);
+        assert_eq!(messages[0].metrics()["success"], PayloadValue::Uint8(1));
+        assert_eq!(messages[1].metrics()["success"], PayloadValue::Uint8(0));
+    }
+
+    #[test]
+    fn collectors_run_at_own_intervals() {
+        let mut scheduler = CollectorScheduler::new();
+        scheduler.register(NamedCollector { name: "a" }, Duration::from_secs(5));
+        scheduler.register(NamedCollector { name: "b" }, Duration::from_secs(10));
+        let start = scheduler.collectors[0].next_run;
+        for scheduled in scheduler.collectors.iter_mut() {
+            scheduled.next_run = start;
+        }
+
+        let mut sources = Vec::new();
+        for _ in 0..6 {
+            let scheduled = next_due(&mut scheduler.collectors);
+            sources.push(scheduled.collector.new_message().source().to_string());
+            // Every collection takes a second
+            let finished = scheduled.next_run + Duration::from_secs(1);
+            scheduled.reschedule(finished);
+        }
+
+        assert_eq!(sources, vec!["a", "b", "a", "a", "b", "a"]);
+        assert_eq!(
+            scheduler.collectors[0].next_run,
+            start + Duration::from_secs(20)
+        );
+    }
+
+    #[test]
+    fn exporters_disconnecting_stops_scheduler() {
+        let mut scheduler = CollectorScheduler::new();
+        scheduler.register(NamedCollector { name: "a" }, Duration::from_millis(10));
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let handle = thread::spawn(move || scheduler.start(vec![sender], "host".to_string()));
+
+        let msg = receiver.recv().unwrap();
+        drop(receiver);
+        handle.join().unwrap();
+
+        assert_eq!(msg.source(), "a");
+        assert_eq!(msg.tags()["hostname"], "host");
+    }
+
+    #[test]
+    fn next_deadline_is_fixed_rate() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(10);
+
+        assert_eq!(
+            next_deadline(start, interval, start + Duration::from_secs(3)),
+            start + interval
+        );
+        assert_eq!(
+            next_deadline(start, interval, start + Duration::from_secs(10)),
+            start + Duration::from_secs(20)
+        );
+        assert_eq!(
+            next_deadline(start, interval, start + Duration::from_secs(35)),
+            start + Duration::from_secs(40)
+        );
+    }
+
+    #[test]
+    fn failed_collection_produces_message() {
+        let collector = FailingCollector {};
+        let err = collector.collect().unwrap_err();
+        let msg = failure_message(&collector, &err);
+
+        assert_eq!(msg.source(), "failing");
+        assert_eq!(msg.tags()["host"], "localhost");
+        assert_eq!(msg.tags()["error_class"], "timeout");
+        assert_eq!(msg.metrics()["success"], PayloadValue::Uint8(0));
+        assert_eq!(
+            msg.metrics()["error"],
+            PayloadValue::String(err.to_string())
+        );
+    }
+
+    #[rstest]
+    #[case(100, 150, Some(50))]
+    #[case(100, 100, Some(0))]
+    #[case(u64::from(u32::MAX) - 9, 10, Some(20))]
+    #[case(1000, 10, None)]
+    #[case(u64::from(u32::MAX) + 100, 10, None)]
+    fn delta_of_32_bit_counter(
+        #[case] previous: u64,
+        #[case] current: u64,
+        #[case] expected: Option<u64>,
+    ) {
+        assert_eq!(
+            wrapping_delta(previous, current, u64::from(u32::MAX)),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(100, 150, Some(50))]
+    #[case(u64::MAX - 9, 10, Some(20))]
+    #[case(u64::from(u32::MAX) - 9, 10, None)]
+    #[case(u64::from(u32::MAX) + 100, 10, None)]
+    fn delta_of_64_bit_counter(
+        #[case] previous: u64,
+        #[case] current: u64,
+        #[case] expected: Option<u64>,
+    ) {
+        assert_eq!(wrapping_delta(previous, current, u64::MAX), expected);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn decrease_of_kernel_counter_is_reset() {
+        assert_eq!(counter_delta(u64::from(u32::MAX) - 9, 10), None);
+    }
+}
@@ -0,0 +1,261 @@
+//! Network interface collector reads the interface counters from
+//! `/proc/net/dev` and the link state from `/sys/class/net`. All interfaces
+//! are reported unless the interfaces are listed in the configuration.
+//!
+//! Counters are reported as they are and as rates per second since the
+//! previous collection. Rates are not reported on the first collection or
+//! when the interface was reset. Counters are expected to wrap around at 32
+//! bits only on 32-bit targets and other decreases are treated as resets.
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::debug;
+
+use super::{counter_delta, Collector};
+use crate::error::{Error, Result, ResultError};
+use crate::message::Message;
+
+const PROC_NET_DEV: &str = "/proc/net/dev";
+const SYS_CLASS_NET: &str = "/sys/class/net";
+/// Reported counters and their columns in `/proc/net/dev`.
+const COUNTERS: [(&str, usize); 8] = [
+    ("rx_bytes", 0),
+    ("rx_packets", 1),
+    ("rx_errors", 2),
+    ("rx_dropped", 3),
+    ("tx_bytes", 8),
+    ("tx_packets", 9),
+    ("tx_errors", 10),
+    ("tx_dropped", 11),
+];
+
+type Counters = [u64; COUNTERS.len()];
+
+pub struct Netdev {
+    interfaces: Vec<String>,
+    proc_net_dev: PathBuf,
+    sys_class_net: PathBuf,
+    previous: Mutex<HashMap<String, Sample>>,
+}
+
+/// Counters of an interface on the previous collection.
+struct Sample {
+    time: Instant,
+    ifindex: Option<u32>,
+    counters: Counters,
+}
+
+impl Netdev {
+    pub fn new(interfaces: Vec<String>) -> Self {
+        Self {
+            interfaces,
+            proc_net_dev: PathBuf::from(PROC_NET_DEV),
+            sys_class_net: PathBuf::from(SYS_CLASS_NET),
+            previous: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn read_counters(&self) -> Result<Vec<(String, Counters)>> {
+        let contents = fs::read_to_string(&self.proc_net_dev).map_err(|err| {
+            Error::new("Failed to read interface counters")
+                .set_cause(err)
+                .set_context(&self.proc_net_dev.display().to_string())
+        })?;
+        let interfaces: Vec<_> = parse_proc_net_dev(&contents)
+            .into_iter()
+            .filter(|(name, _)| self.interfaces.is_empty() || self.interfaces.contains(name))
+            .collect();
+        if interfaces.is_empty() {
+            return Err(Error::new("No network interfaces found"));
+        }
+        Ok(interfaces)
+    }
+
+    /// Reads an interface attribute from sysfs. Some attributes can't be read
+    /// in every state, e.g. `carrier` of an interface which is down.
+    fn read_attribute(&self, interface: &str, name: &str) -> Option<String> {
+        let path = self.sys_class_net.join(interface).join(name);
+        match fs::read_to_string(&path) {
+            Ok(value) => Some(value.trim().to_string()),
+            Err(err) => {
+                debug!("Failed to read {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+}
+
+impl Collector for Netdev {
+    fn collect(&self) -> Result<Vec<Message>> {
+        let interfaces = self.read_counters().set_source("netdev_collector")?;
+        let now = Instant::now();
+        let mut previous = self.previous.lock().unwrap();
+        let mut samples = HashMap::new();
+
+        let mut messages = Vec::new();
+        for (interface, counters) in interfaces {
+            let mut message = self.new_message();
+            message.insert_tag("interface", &interface);
+            for ((name, _), value) in COUNTERS.iter().zip(counters) {
+                message.insert_metric(name, value);
+            }
+
+            let ifindex = self
+                .read_attribute(&interface, "ifindex")
+                .and_then(|value| value.parse().ok());
+            // Recreated interface has a new index and its counters start over.
+            let sample = previous
+                .remove(&interface)
+                .filter(|sample| sample.ifindex == ifindex);
+            if let Some(sample) = sample {
+                let elapsed = now.duration_since(sample.time).as_secs_f64();
+                let deltas: Option<Vec<u64>> = sample
+                    .counters
+                    .iter()
+                    .zip(counters)
+                    .map(|(previous, current)| counter_delta(*previous, current))
+                    .collect();
+                match deltas {
+                    Some(deltas) if elapsed > 0.0 => {
+                        for ((name, _), delta) in COUNTERS.iter().zip(deltas) {
+                            message.insert_metric(
+                                &format!("{}_per_second", name),
+                                delta as f64 / elapsed,
+                            );
+                        }
+                    }
+                    _ => debug!("Counters of interface {} were reset", interface),
+                }
+            }
+
+            if let Some(carrier) = self
+                .read_attribute(&interface, "carrier")
+                .and_then(|value| value.parse::<u8>().ok())
+            {
+                message.insert_metric("carrier", carrier);
+            }
+            if let Some(mtu) = self
+                .read_attribute(&interface, "mtu")
+                .and_then(|value| value.parse::<u32>().ok())
+            {
+                message.insert_metric("mtu", mtu);
+            }
+            // Speed is -1 or can't be read when it's unknown.
+            if let Some(speed) = self
+                .read_attribute(&interface, "speed")
+                .and_then(|value| value.parse::<u32>().ok())
+            {
+                message.insert_metric("speed", speed);
+            }
+            if let Some(operstate) = self.read_attribute(&interface, "operstate") {
+                message.insert_metric("operstate", operstate);
+            }
+
+            samples.insert(
+                interface,
+                Sample {
+                    time: now,
+                    ifindex,
+                    counters,
+                },
+            );
+            messages.push(message);
+        }
+        // Interfaces which disappeared are forgotten.
+        *previous = samples;
+        Ok(messages)
+    }
+
+    fn new_message(&self) -> Message {
+        Message::new("netdev")
+    }
+}
+
+/// Parses the interface names and counters from `/proc/net/dev` contents.
+fn parse_proc_net_dev(contents: &str) -> Vec<(String, Counters)> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (name, values) = line.split_once(':')?;
+            let values: Vec<u64> = values
+                .split_whitespace()
+                .map(|value| value.parse().ok())
+                .collect::<Option<_>>()?;
+            let mut counters = Counters::default();
+            for (counter, (_, column)) in counters.iter_mut().zip(COUNTERS) {
+                *counter = *values.get(column)?;
+            }
+            Some((name.trim().to_string(), counters))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::PayloadValue;
+    use crate::test_util::TempDir;
+
+    const PROC_NET_DEV_CONTENTS: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:    1000      10    0    0    0     0          0         0     1000      10    0    0    0     0       0          0
+  eth0: 74964428    5834    1    2    0     0          0         0   478529    5893    3    4    0     0       0          0
+";
+
+    #[test]
+    fn parse_counters() {
+        let interfaces = parse_proc_net_dev(PROC_NET_DEV_CONTENTS);
+
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(interfaces[0].0, "lo");
+        assert_eq!(
+            interfaces[1],
+            (
+                "eth0".to_string(),
+                [74964428, 5834, 1, 2, 478529, 5893, 3, 4]
+            )
+        );
+    }
+
+    #[test]
+    fn collect_rates_on_second_collection() {
+        let dir = TempDir::new("netdev");
+        fs::create_dir_all(dir.join("eth0")).unwrap();
+        fs::write(dir.join("eth0/mtu"), "1500\n").unwrap();
+        fs::write(dir.join("eth0/speed"), "-1\n").unwrap();
+        fs::write(dir.join("eth0/operstate"), "up\n").unwrap();
+        fs::write(dir.join("dev"), PROC_NET_DEV_CONTENTS).unwrap();
+        let mut netdev = Netdev::new(vec!["eth0".to_string()]);
+        netdev.proc_net_dev = dir.join("dev");
+        netdev.sys_class_net = dir.path().to_path_buf();
+
+        let msg = netdev.collect().unwrap().pop().unwrap();
+        assert_eq!(msg.source(), "netdev");
+        assert_eq!(msg.tags()["interface"], "eth0");
+        assert_eq!(msg.metrics()["rx_errors"], PayloadValue::Uint64(1));
+        assert_eq!(msg.metrics()["mtu"], PayloadValue::Uint32(1500));
+        assert_eq!(msg.metrics()["operstate"], PayloadValue::from("up"));
+        assert!(msg.metrics().get("speed").is_none());
+        assert!(msg.metrics().get("carrier").is_none());
+        assert!(msg.metrics().get("rx_bytes_per_second").is_none());
+
+        let msgs = netdev.collect().unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(
+            msgs[0].metrics()["rx_bytes_per_second"],
+            PayloadValue::Float64(0.0)
+        );
+    }
+
+    #[test]
+    fn collect_missing_interface() {
+        let netdev = Netdev::new(vec!["missing0".to_string()]);
+        let err = netdev.collect().unwrap_err();
+
+        assert_eq!(err.source().as_ref().unwrap(), "netdev_collector");
+    }
+}
@@ -0,0 +1,201 @@
+//! Netstat collector reads the network stack counters from `/proc/net/snmp`
+//! and `/proc/net/netstat`. Counters are reported as their increase since the
+//! previous collection and as rates per second so nothing is reported on the
+//! first collection.
+//!
+//! Retransmissions, resets and dropped datagrams often reveal link problems
+//! which don't show up in the latency of a single probe.
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::debug;
+
+use super::{counter_delta, Collector};
+use crate::error::{Error, Result, ResultError};
+use crate::message::Message;
+
+const PROC_NET_SNMP: &str = "/proc/net/snmp";
+const PROC_NET_NETSTAT: &str = "/proc/net/netstat";
+/// Reported counters and their names in the proc files.
+const COUNTERS: [(&str, &str); 12] = [
+    ("Tcp.OutSegs", "tcp_out_segments"),
+    ("Tcp.RetransSegs", "tcp_retransmitted_segments"),
+    // Established connections which were reset by either end
+    ("Tcp.EstabResets", "tcp_established_resets"),
+    ("Tcp.OutRsts", "tcp_out_resets"),
+    ("Tcp.InErrs", "tcp_in_errors"),
+    ("TcpExt.ListenOverflows", "tcp_listen_overflows"),
+    ("TcpExt.ListenDrops", "tcp_listen_drops"),
+    ("Udp.InErrors", "udp_in_errors"),
+    ("Udp.RcvbufErrors", "udp_receive_buffer_errors"),
+    ("Icmp.InErrors", "icmp_in_errors"),
+    ("Icmp.OutErrors", "icmp_out_errors"),
+    ("Icmp.InDestUnreachs", "icmp_in_destination_unreachable"),
+];
+
+pub struct Netstat {
+    paths: Vec<PathBuf>,
+    previous: Mutex<Option<Sample>>,
+}
+
+/// Counters on the previous collection.
+struct Sample {
+    time: Instant,
+    counters: HashMap<String, u64>,
+}
+
+impl Netstat {
+    pub fn new() -> Self {
+        Self {
+            paths: vec![
+                PathBuf::from(PROC_NET_SNMP),
+                PathBuf::from(PROC_NET_NETSTAT),
+            ],
+            previous: Mutex::new(None),
+        }
+    }
+
+    fn read_counters(&self) -> Result<HashMap<String, u64>> {
+        let mut counters = HashMap::new();
+        for path in self.paths.iter() {
+            let contents = fs::read_to_string(path).map_err(|err| {
+                Error::new("Failed to read network stack counters")
+                    .set_cause(err)
+                    .set_context(&path.display().to_string())
+            })?;
+            counters.extend(parse_counters(&contents));
+        }
+        Ok(counters)
+    }
+}
+
+impl Collector for Netstat {
+    fn collect(&self) -> Result<Vec<Message>> {
+        let counters = self.read_counters().set_source("netstat_collector")?;
+        let now = Instant::now();
+        let sample = Sample {
+            time: now,
+            counters: counters.clone(),
+        };
+        let Some(previous) = self.previous.lock().unwrap().replace(sample) else {
+            return Ok(Vec::new());
+        };
+
+        let elapsed = now.duration_since(previous.time).as_secs_f64();
+        let mut message = self.new_message();
+        for (counter, name) in COUNTERS {
+            let (Some(previous), Some(current)) =
+                (previous.counters.get(counter), counters.get(counter))
+            else {
+                continue;
+            };
+            match counter_delta(*previous, *current) {
+                Some(delta) => {
+                    message.insert_metric(name, delta);
+                    if elapsed > 0.0 {
+                        message
+                            .insert_metric(&format!("{}_per_second", name), delta as f64 / elapsed);
+                    }
+                }
+                None => debug!("Counter {} was reset", counter),
+            }
+        }
+        Ok(vec![message])
+    }
+
+    fn new_message(&self) -> Message {
+        Message::new("netstat")
+    }
+}
+
+/// Parses counters from `/proc/net/snmp` or `/proc/net/netstat` contents.
+/// Counters are listed in pairs of lines where the first line has the names
+/// and the second the values, e.g. `Tcp: RtoAlgorithm RtoMin` and
+/// `Tcp: 1 200`. Counters are keyed by `<prefix>.<name>`. Negative values
+/// are not counters and they are skipped.
+fn parse_counters(contents: &str) -> HashMap<String, u64> {
+    let mut counters = HashMap::new();
+    let mut lines = contents.lines();
+    while let (Some(names), Some(values)) = (lines.next(), lines.next()) {
+        let (Some((prefix, names)), Some((value_prefix, values))) =
+            (names.split_once(':'), values.split_once(':'))
+        else {
+            continue;
+        };
+        if prefix != value_prefix {
+            debug!("Unexpected network stack counter line: {}", values);
+            continue;
+        }
+        for (name, value) in names.split_whitespace().zip(values.split_whitespace()) {
+            if let Ok(value) = value.parse() {
+                counters.insert(format!("{}.{}", prefix, name), value);
+            }
+        }
+    }
+    counters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::PayloadValue;
+    use crate::test_util::TempDir;
+
+    const SNMP: &str = "\
+Tcp: RtoAlgorithm RtoMin RtoMax MaxConn ActiveOpens PassiveOpens AttemptFails EstabResets CurrEstab InSegs OutSegs RetransSegs InErrs OutRsts InCsumErrors
+Tcp: 1 200 120000 -1 757 668 81 93 2 22946 23053 1 0 153 0
+Udp: InDatagrams NoPorts InErrors OutDatagrams RcvbufErrors SndbufErrors InCsumErrors IgnoredMulti MemErrors
+Udp: 205 2 0 218 0 0 0 0 0
+";
+
+    #[test]
+    fn parse_snmp_counters() {
+        let counters = parse_counters(SNMP);
+
+        assert_eq!(counters["Tcp.RetransSegs"], 1);
+        assert_eq!(counters["Tcp.OutRsts"], 153);
+        assert_eq!(counters["Udp.NoPorts"], 2);
+        assert!(!counters.contains_key("Tcp.MaxConn"));
+    }
+
+    #[test]
+    fn collect_counter_increase() {
+        let dir = TempDir::new("netstat");
+        let path = dir.join("snmp");
+        fs::write(&path, SNMP).unwrap();
+        let mut netstat = Netstat::new();
+        netstat.paths = vec![path.clone()];
+
+        assert!(netstat.collect().unwrap().is_empty());
+
+        fs::write(&path, SNMP.replace(" 1 0 153 0", " 6 0 150 0")).unwrap();
+        let msg = netstat.collect().unwrap().pop().unwrap();
+        assert_eq!(msg.source(), "netstat");
+        assert_eq!(
+            msg.metrics()["tcp_retransmitted_segments"],
+            PayloadValue::Uint64(5)
+        );
+        assert_eq!(msg.metrics()["udp_in_errors"], PayloadValue::Uint64(0));
+        assert!(msg
+            .metrics()
+            .get("tcp_retransmitted_segments_per_second")
+            .is_some());
+        assert!(msg.metrics().get("tcp_out_resets_per_second").is_none());
+        assert!(msg.metrics().get("tcp_out_resets").is_none());
+        assert!(msg.metrics().get("tcp_listen_overflows").is_none());
+    }
+
+    #[test]
+    #[ignore]
+    fn collect_from_proc() {
+        let netstat = Netstat::new();
+        netstat.collect().unwrap();
+        let msg = netstat.collect().unwrap().pop().unwrap();
+
+        assert!(msg.metrics().get("tcp_retransmitted_segments").is_some());
+        assert!(msg.metrics().get("tcp_listen_overflows").is_some());
+    }
+}
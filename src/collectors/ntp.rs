@@ -0,0 +1,245 @@
+//! NTP collector queries a defined server with SNTP and records the offset of
+//! the local clock in `offset` and the round-trip delay in `delay`
+//! (milliseconds). `reachable` tells whether the server replied.
+//!
+//! Devices without a real-time clock can have badly drifted clocks which
+//! corrupt the timestamps of all messages. Clock guard keeps the latest
+//! measured offset of every server and flags or drops collected messages
+//! while the median of the offsets is over the configured limit. Offsets of
+//! servers which haven't replied for a few intervals are forgotten. NTP
+//! messages are never dropped so that the drift itself stays visible.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+
+use super::{failure_message, Collector};
+use crate::config::{ClockGuardAction, Timeout};
+use crate::error::{Result, ResultError};
+use crate::message::Message;
+use crate::net::{self, ntp, IpFamily};
+use crate::url::Host;
+
+const NTP_PORT: u16 = 123;
+/// Number of collection intervals after which an offset expires.
+const OFFSET_MAX_AGE_INTERVALS: u32 = 3;
+
+pub struct Ntp {
+    server: Host,
+    family: Option<IpFamily>,
+    timeout: Duration,
+    guard: Option<ClockGuard>,
+}
+
+impl Ntp {
+    pub fn new(
+        server: Host,
+        family: Option<IpFamily>,
+        timeout: Timeout,
+        guard: Option<ClockGuard>,
+    ) -> Ntp {
+        Ntp {
+            server,
+            family,
+            timeout: Duration::from_secs(timeout.into()),
+            guard,
+        }
+    }
+
+    fn query(&self) -> Result<ntp::NtpResponse> {
+        let addr = SocketAddr::new(net::resolve(&self.server, self.family)?, NTP_PORT);
+        ntp::query(addr, self.timeout)
+    }
+}
+
+impl Collector for Ntp {
+    fn collect(&self) -> Result<Vec<Message>> {
+        let response = match self.query().set_source("ntp_collector") {
+            Ok(response) => response,
+            Err(err) => {
+                error!("{}", err);
+                let mut message = failure_message(self, &err);
+                message.insert_metric("reachable", 0u8);
+                return Ok(vec![message]);
+            }
+        };
+
+        let offset = response.offset * 1000.0;
+        if let Some(guard) = self.guard.as_ref() {
+            let server = match self.family {
+                Some(family) => format!("{} {}", self.server, family),
+                None => self.server.to_string(),
+            };
+            guard.update(&server, offset);
+        }
+        let mut message = self.new_message();
+        message.insert_metric("reachable", 1u8);
+        message.insert_metric("offset", offset);
+        message.insert_metric("delay", response.delay * 1000.0);
+        message.insert_metric("stratum", response.stratum);
+        Ok(vec![message])
+    }
+
+    fn new_message(&self) -> Message {
+        let mut message = Message::new("ntp");
+        message.insert_tag("server", &self.server.to_string());
+        if let Some(family) = self.family {
+            message.insert_tag("address_family", &family.to_string());
+        }
+        message
+    }
+}
+
+/// Flags or drops collected messages while the local clock offset is over
+/// the limit. Clones share the measured offsets.
+#[derive(Clone)]
+pub struct ClockGuard {
+    /// Maximum offset in milliseconds.
+    max_offset: f64,
+    action: ClockGuardAction,
+    max_age: Duration,
+    /// Latest offset of every server and the time it was measured.
+    offsets: Arc<Mutex<HashMap<String, (f64, Instant)>>>,
+}
+
+impl ClockGuard {
+    /// Creates a guard for servers which are queried at most `interval`
+    /// apart.
+    pub fn new(max_offset: u64, action: ClockGuardAction, interval: Duration) -> Self {
+        Self {
+            max_offset: max_offset as f64,
+            action,
+            max_age: interval * OFFSET_MAX_AGE_INTERVALS,
+            offsets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn update(&self, server: &str, offset: f64) {
+        let mut offsets = self.offsets.lock().unwrap();
+        let was_exceeded = self.exceeded(&offsets);
+        offsets.insert(server.to_string(), (offset, Instant::now()));
+        if self.exceeded(&offsets) && !was_exceeded {
+            warn!(
+                "Local clock offset {:.0} ms exceeds the limit of {} ms",
+                self.median(&offsets).unwrap_or(offset),
+                self.max_offset
+            );
+        }
+    }
+
+    /// Returns the median of the offsets which have not expired.
+    fn median(&self, offsets: &HashMap<String, (f64, Instant)>) -> Option<f64> {
+        let mut current: Vec<f64> = offsets
+            .values()
+            .filter(|(_, measured)| measured.elapsed() < self.max_age)
+            .map(|(offset, _)| *offset)
+            .collect();
+        if current.is_empty() {
+            return None;
+        }
+        current.sort_by(f64::total_cmp);
+        let len = current.len();
+        Some((current[(len - 1) / 2] + current[len / 2]) / 2.0)
+    }
+
+    fn exceeded(&self, offsets: &HashMap<String, (f64, Instant)>) -> bool {
+        self.median(offsets)
+            .is_some_and(|offset| offset.abs() > self.max_offset)
+    }
+
+    /// Returns the message flagged with `clock_offset_exceeded` tag or `None`
+    /// if the message is dropped. Messages pass as they are while no offset
+    /// has been measured.
+    pub fn apply(&self, mut message: Message) -> Option<Message> {
+        let exceeded = self.exceeded(&self.offsets.lock().unwrap());
+        if !exceeded {
+            return Some(message);
+        }
+        match self.action {
+            ClockGuardAction::Drop if message.source() != "ntp" => None,
+            _ => {
+                message.insert_tag("clock_offset_exceeded", "true");
+                Some(message)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::PayloadValue;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    fn is_flagged(guard: &ClockGuard) -> bool {
+        let message = guard.apply(Message::new("ping")).unwrap();
+        message.tags().get("clock_offset_exceeded").is_some()
+    }
+
+    #[test]
+    fn guard_flags_messages() {
+        let guard = ClockGuard::new(1000, ClockGuardAction::Flag, INTERVAL);
+        assert!(!is_flagged(&guard));
+
+        guard.update("a", -1500.0);
+        assert!(is_flagged(&guard));
+
+        guard.update("a", 20.0);
+        assert!(!is_flagged(&guard));
+    }
+
+    #[test]
+    fn guard_uses_median_offset() {
+        let guard = ClockGuard::new(1000, ClockGuardAction::Flag, INTERVAL);
+        guard.update("a", 1500.0);
+        guard.update("b", 1400.0);
+        guard.update("c", 10.0);
+        assert!(is_flagged(&guard));
+
+        guard.update("b", -20.0);
+        assert!(!is_flagged(&guard));
+    }
+
+    #[test]
+    fn guard_forgets_expired_offsets() {
+        let guard = ClockGuard::new(1000, ClockGuardAction::Flag, Duration::ZERO);
+        guard.update("a", 1500.0);
+
+        assert!(!is_flagged(&guard));
+    }
+
+    #[test]
+    fn guard_drops_messages() {
+        let guard = ClockGuard::new(1000, ClockGuardAction::Drop, INTERVAL);
+        guard.clone().update("a", 1500.0);
+
+        assert!(guard.apply(Message::new("ping")).is_none());
+        let message = guard.apply(Message::new("ntp")).unwrap();
+        assert_eq!(message.tags()["clock_offset_exceeded"], "true");
+    }
+
+    #[test]
+    fn collect_unreachable_server() {
+        let mut ntp = Ntp::new("127.0.0.1".parse().unwrap(), None, Timeout(1), None);
+        ntp.timeout = Duration::from_millis(10);
+        let msg = ntp.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.source(), "ntp");
+        assert_eq!(msg.tags()["server"], "127.0.0.1");
+        assert_eq!(msg.metrics()["reachable"], PayloadValue::Uint8(0));
+        assert_eq!(msg.metrics()["success"], PayloadValue::Uint8(0));
+    }
+
+    #[test]
+    #[ignore]
+    fn ntp_collect() {
+        let ntp = Ntp::new("pool.ntp.org".parse().unwrap(), None, Timeout(1), None);
+        let msg = ntp.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.metrics()["reachable"], PayloadValue::Uint8(1));
+        assert!(msg.metrics().get("offset").is_some());
+    }
+}
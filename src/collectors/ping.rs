@@ -1,122 +1,218 @@
-//! Ping collector sends ICMP echo message to a defined host and records the
-//! round-trip-time. This collector uses the OS `ping` command and parses the
-//! response from stdout.
-use std::process::Command;
-
-use regex::Regex;
+//! Ping collector sends ICMP echo messages to a defined host and records the
+//! packet loss and round-trip-time statistics.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
 use super::Collector;
 use crate::config::Timeout;
-use crate::error::{Error, Result, ResultError};
+use crate::error::{Result, ResultError};
 use crate::message::Message;
+use crate::net::{self, IcmpSocket, IpFamily};
 use crate::url::Host;
 
 pub struct Ping {
     host: Host,
-    timeout: u64,
+    family: Option<IpFamily>,
+    timeout: Duration,
+    packet_count: u16,
+    packet_interval: Duration,
 }
 
 impl Ping {
-    pub fn new(host: Host, timeout: Timeout) -> Ping {
+    pub fn new(
+        host: Host,
+        family: Option<IpFamily>,
+        timeout: Timeout,
+        packet_count: u16,
+        packet_interval: u64,
+    ) -> Ping {
         Ping {
             host,
-            timeout: timeout.into(),
+            family,
+            timeout: Duration::from_secs(timeout.into()),
+            packet_count,
+            packet_interval: Duration::from_millis(packet_interval),
         }
     }
 
-    fn get_ping_latency(&self) -> Result<f64> {
-        let output = self.execute_ping_on_command_line()?;
-        self.parse_latency_from_ping_output(&output)
-    }
-
-    fn execute_ping_on_command_line(&self) -> Result<String> {
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(format!(
-                "ping {} -q -c 1 -w {}",
-                &self.host.to_string(),
-                self.timeout
-            ))
-            .output()
-            .map_err(|e| Error::new("Failed to execute ping command").set_cause(e))?;
-
-        let stderr = String::from_utf8(output.stderr).expect("Ping output not valid utf8");
-        if !stderr.is_empty() {
-            return Err(Error::new("Ping command returned an error").set_context(stderr.trim()));
+    /// Sends echo requests in fixed intervals and waits for the replies
+    /// in between. Replies are waited until timeout after the last request.
+    fn send_pings(&self, addr: SocketAddr) -> Result<PingStatistics> {
+        let socket = IcmpSocket::connect(addr)?;
+
+        let mut pending = HashMap::new();
+        let mut rtts = Vec::new();
+        for sequence in 0..self.packet_count {
+            let sent = Instant::now();
+            socket.send_echo_request(sequence)?;
+            pending.insert(sequence, sent);
+
+            let deadline = if sequence + 1 < self.packet_count {
+                sent + self.packet_interval
+            } else {
+                sent + self.timeout
+            };
+            while !pending.is_empty() {
+                match socket.recv_echo_reply(deadline)? {
+                    Some(reply_sequence) => {
+                        if let Some(sent) = pending.remove(&reply_sequence) {
+                            rtts.push(sent.elapsed());
+                        }
+                    }
+                    None => break,
+                }
+            }
+            let now = Instant::now();
+            if now < deadline && sequence + 1 < self.packet_count {
+                std::thread::sleep(deadline - now);
+            }
         }
 
-        let output = String::from_utf8(output.stdout).expect("Ping output not valid utf8");
-
-        Ok(output)
-    }
-
-    fn parse_latency_from_ping_output(&self, ping_output: &str) -> Result<f64> {
-        let last_line = ping_output
-            .lines()
-            .last()
-            .ok_or_else(|| Error::new("Empty ping command output"))?;
-
-        let captures = Self::latency_regex().captures(last_line).ok_or_else(|| {
-            Error::new("Failed to parse ping command output").set_context(last_line.trim())
-        })?;
-
-        let latency = captures.get(1).map(|m| m.as_str()).unwrap();
-
-        latency.parse().map_err(|e| {
-            Error::new("Failed to convert ping latency to a number")
-                .set_cause(e)
-                .set_context(latency)
-        })
-    }
-
-    fn latency_regex() -> Regex {
-        Regex::new(r#"= (\d+\.\d+)/"#).expect("Failed to compile regular expression")
+        Ok(PingStatistics::new(self.packet_count, &rtts))
     }
 }
 
 impl Collector for Ping {
     fn collect(&self) -> Result<Vec<Message>> {
-        let latency = self.get_ping_latency().set_source("ping_collector")?;
+        let (addr, gateway) =
+            net::resolve_with_gateway(&self.host, self.family, 0).set_source("ping_collector")?;
+        let stats = self.send_pings(addr).set_source("ping_collector")?;
+
+        let mut message = self.new_message();
+        if let Some(gateway) = gateway {
+            message.insert_tag("gateway_ip", &gateway.addr.to_string());
+            message.insert_tag("gateway_interface", &gateway.interface);
+        }
+        message.insert_metric("packets_sent", stats.packets_sent);
+        message.insert_metric("packets_received", stats.packets_received);
+        message.insert_metric("packet_loss_percent", stats.packet_loss_percent());
+        if let Some(rtt) = stats.rtt {
+            message.insert_metric("latency", rtt.avg);
+            message.insert_metric("rtt_min", rtt.min);
+            message.insert_metric("rtt_avg", rtt.avg);
+            message.insert_metric("rtt_max", rtt.max);
+            message.insert_metric("rtt_mdev", rtt.mdev);
+            if let Some(jitter) = rtt.jitter {
+                message.insert_metric("jitter", jitter);
+            }
+        }
+        Ok(vec![message])
+    }
 
+    fn new_message(&self) -> Message {
         let mut message = Message::new("ping");
-        message.insert_metric("latency", latency);
         message.insert_tag("host", &self.host.to_string());
-        Ok(vec![message])
+        if let Some(family) = self.family {
+            message.insert_tag("address_family", &family.to_string());
+        }
+        message
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) struct PingStatistics {
+    pub(super) packets_sent: u16,
+    pub(super) packets_received: u16,
+    pub(super) rtt: Option<RttStatistics>,
+}
+
+/// Round-trip-time statistics in milliseconds.
+#[derive(Debug, PartialEq)]
+pub(super) struct RttStatistics {
+    pub(super) min: f64,
+    pub(super) avg: f64,
+    pub(super) max: f64,
+    pub(super) mdev: f64,
+    /// Mean difference between consecutive round-trip-times. Available when
+    /// at least two replies were received.
+    pub(super) jitter: Option<f64>,
+}
+
+impl PingStatistics {
+    pub(super) fn new(packets_sent: u16, rtts: &[Duration]) -> Self {
+        let rtts: Vec<f64> = rtts.iter().map(|rtt| rtt.as_secs_f64() * 1000.0).collect();
+        let rtt = if rtts.is_empty() {
+            None
+        } else {
+            let n = rtts.len() as f64;
+            let avg = rtts.iter().sum::<f64>() / n;
+            let squared_avg = rtts.iter().map(|rtt| rtt * rtt).sum::<f64>() / n;
+            let jitter = if rtts.len() > 1 {
+                let diffs: f64 = rtts.windows(2).map(|w| (w[1] - w[0]).abs()).sum();
+                Some(diffs / (n - 1.0))
+            } else {
+                None
+            };
+
+            Some(RttStatistics {
+                min: rtts.iter().copied().fold(f64::INFINITY, f64::min),
+                avg,
+                max: rtts.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                mdev: (squared_avg - avg * avg).max(0.0).sqrt(),
+                jitter,
+            })
+        };
+
+        PingStatistics {
+            packets_sent,
+            packets_received: rtts.len() as u16,
+            rtt,
+        }
+    }
+
+    pub(super) fn packet_loss_percent(&self) -> f64 {
+        let lost = self.packets_sent - self.packets_received;
+        f64::from(lost) / f64::from(self.packets_sent) * 100.0
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use rstest::*;
-
     use super::*;
 
-    #[fixture]
-    fn ping_output() -> &'static str {
-        "PING localhost (127.0.0.1) 56(84) bytes of data.
-
-        --- 127.0.0.1 ping statistics ---
-        1 packets transmitted, 1 received, 0% packet loss, time 0ms
-        rtt min/avg/max/mdev = 10.192/10.192/10.192/0.000 ms"
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn statistics_from_rtts() {
+        let rtts = [10, 20, 15].map(Duration::from_millis);
+        let stats = PingStatistics::new(4, &rtts);
+        let rtt = stats.rtt.as_ref().unwrap();
+
+        assert_eq!(stats.packets_received, 3);
+        assert_eq!(stats.packet_loss_percent(), 25.0);
+        assert_eq!(rtt.min, 10.0);
+        assert_eq!(rtt.avg, 15.0);
+        assert_eq!(rtt.max, 20.0);
+        assert!((rtt.mdev - 4.0825).abs() < 0.001);
+        assert_eq!(rtt.jitter, Some(7.5));
     }
 
-    #[rstest]
+    #[test]
     #[allow(clippy::float_cmp)]
-    fn ping_output_parsing_successful(ping_output: &str) {
-        let ping = Ping::new("localhost".parse().unwrap(), Timeout(1));
-        let result = ping.parse_latency_from_ping_output(ping_output).unwrap();
+    fn statistics_with_full_packet_loss() {
+        let stats = PingStatistics::new(3, &[]);
+
+        assert_eq!(stats.packets_received, 0);
+        assert_eq!(stats.packet_loss_percent(), 100.0);
+        assert!(stats.rtt.is_none());
+    }
+
+    #[test]
+    fn statistics_without_jitter() {
+        let stats = PingStatistics::new(1, &[Duration::from_millis(10)]);
 
-        assert_eq!(result, 10.192);
+        assert_eq!(stats.rtt.unwrap().jitter, None);
     }
 
     #[test]
     #[ignore]
     fn ping_collect() {
-        let ping = Ping::new("localhost".parse().unwrap(), Timeout(1));
+        let ping = Ping::new("localhost".parse().unwrap(), None, Timeout(1), 3, 10);
         let msg = ping.collect().unwrap().pop().unwrap();
 
         assert_eq!(msg.source(), "ping");
         assert!(msg.metrics().get("latency").is_some());
+        assert_eq!(msg.metrics()["packets_received"], 3u16.into());
         assert_eq!(msg.tags()["host"], "localhost");
     }
 }
@@ -0,0 +1,312 @@
+//! System collector reads the health of the device which runs Uption from
+//! `/proc` and `/sys/class/thermal`. Load, memory and uptime are reported as
+//! they are and CPU usage is calculated since the previous collection so it's
+//! not reported on the first collection.
+//!
+//! `temperature` is the highest temperature of the thermal zones in degrees
+//! Celsius. It's not reported when the device has no thermal zones.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::debug;
+
+use super::Collector;
+use crate::error::{Error, ErrorKind, Result, ResultError};
+use crate::message::Message;
+
+const PROC: &str = "/proc";
+const SYS_CLASS_THERMAL: &str = "/sys/class/thermal";
+
+pub struct System {
+    proc_dir: PathBuf,
+    thermal_dir: PathBuf,
+    previous_cpu: Mutex<Option<CpuTimes>>,
+}
+
+/// Aggregated CPU times of all CPUs in clock ticks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CpuTimes {
+    total: u64,
+    idle: u64,
+}
+
+/// Memory and swap usage in bytes.
+#[derive(Debug, PartialEq)]
+struct Memory {
+    total: u64,
+    available: u64,
+    swap_total: u64,
+    swap_free: u64,
+}
+
+impl System {
+    pub fn new() -> Self {
+        Self {
+            proc_dir: PathBuf::from(PROC),
+            thermal_dir: PathBuf::from(SYS_CLASS_THERMAL),
+            previous_cpu: Mutex::new(None),
+        }
+    }
+
+    fn read_proc(&self, name: &str) -> Result<String> {
+        let path = self.proc_dir.join(name);
+        fs::read_to_string(&path).map_err(|err| {
+            Error::new("Failed to read system statistics")
+                .set_cause(err)
+                .set_context(&path.display().to_string())
+        })
+    }
+
+    /// Calculates CPU usage percentage since the previous collection.
+    fn cpu_usage(&self, current: CpuTimes) -> Option<f64> {
+        let mut previous = self.previous_cpu.lock().unwrap();
+        previous.replace(current).and_then(|previous| {
+            let total = current.total.checked_sub(previous.total)?;
+            let idle = current.idle.checked_sub(previous.idle)?;
+            (total > 0).then(|| total.saturating_sub(idle) as f64 / total as f64 * 100.0)
+        })
+    }
+
+    /// Returns the highest temperature of the thermal zones.
+    fn read_temperature(&self) -> Option<f64> {
+        let zones = match fs::read_dir(&self.thermal_dir) {
+            Ok(zones) => zones,
+            Err(err) => {
+                debug!("Failed to read thermal zones: {}", err);
+                return None;
+            }
+        };
+        zones
+            .filter_map(|zone| zone.ok())
+            .filter(|zone| {
+                zone.file_name()
+                    .to_string_lossy()
+                    .starts_with("thermal_zone")
+            })
+            .filter_map(|zone| read_millidegrees(&zone.path().join("temp")))
+            .reduce(f64::max)
+    }
+}
+
+impl Collector for System {
+    fn collect(&self) -> Result<Vec<Message>> {
+        let loadavg = self.read_proc("loadavg").set_source("system_collector")?;
+        let meminfo = self.read_proc("meminfo").set_source("system_collector")?;
+        let stat = self.read_proc("stat").set_source("system_collector")?;
+        let uptime = self.read_proc("uptime").set_source("system_collector")?;
+
+        let mut message = self.new_message();
+        let [load_1, load_5, load_15] = parse_loadavg(&loadavg).set_source("system_collector")?;
+        message.insert_metric("load_1", load_1);
+        message.insert_metric("load_5", load_5);
+        message.insert_metric("load_15", load_15);
+
+        let cpu = parse_cpu_times(&stat).set_source("system_collector")?;
+        if let Some(usage) = self.cpu_usage(cpu) {
+            message.insert_metric("cpu_usage_percent", usage);
+        }
+
+        let memory = parse_meminfo(&meminfo).set_source("system_collector")?;
+        message.insert_metric("memory_total", memory.total);
+        message.insert_metric("memory_available", memory.available);
+        message.insert_metric(
+            "memory_used_percent",
+            memory.total.saturating_sub(memory.available) as f64 / memory.total as f64 * 100.0,
+        );
+        message.insert_metric("swap_total", memory.swap_total);
+        message.insert_metric(
+            "swap_used",
+            memory.swap_total.saturating_sub(memory.swap_free),
+        );
+
+        message.insert_metric(
+            "uptime",
+            parse_uptime(&uptime).set_source("system_collector")?,
+        );
+        if let Some(temperature) = self.read_temperature() {
+            message.insert_metric("temperature", temperature);
+        }
+        Ok(vec![message])
+    }
+
+    fn new_message(&self) -> Message {
+        Message::new("system")
+    }
+}
+
+fn parse_error(name: &str) -> Error {
+    Error::new("Failed to parse system statistics")
+        .set_kind(ErrorKind::Parse)
+        .set_context(name)
+}
+
+/// Parses 1, 5 and 15 minute load averages.
+fn parse_loadavg(contents: &str) -> Result<[f64; 3]> {
+    let loads: Vec<f64> = contents
+        .split_whitespace()
+        .take(3)
+        .filter_map(|load| load.parse().ok())
+        .collect();
+    loads.try_into().map_err(|_| parse_error("loadavg"))
+}
+
+/// Parses the aggregated CPU times from the `cpu` line. Time spent waiting for
+/// IO is idle time.
+fn parse_cpu_times(contents: &str) -> Result<CpuTimes> {
+    let times: Vec<u64> = contents
+        .lines()
+        .find_map(|line| line.strip_prefix("cpu "))
+        .ok_or_else(|| parse_error("stat"))?
+        .split_whitespace()
+        .map(|time| time.parse().map_err(|_| parse_error("stat")))
+        .collect::<Result<_>>()?;
+    if times.len() < 5 {
+        return Err(parse_error("stat"));
+    }
+    // Guest times are included in user times.
+    Ok(CpuTimes {
+        total: times.iter().take(8).sum(),
+        idle: times[3] + times[4],
+    })
+}
+
+fn parse_meminfo(contents: &str) -> Result<Memory> {
+    let value = |key: &str| -> Result<u64> {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+            .and_then(|value| {
+                value
+                    .trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .map(|kilobytes| kilobytes * 1024)
+            .ok_or_else(|| parse_error("meminfo"))
+    };
+    Ok(Memory {
+        total: value("MemTotal")?,
+        available: value("MemAvailable")?,
+        swap_total: value("SwapTotal")?,
+        swap_free: value("SwapFree")?,
+    })
+}
+
+/// Parses the uptime in whole seconds.
+fn parse_uptime(contents: &str) -> Result<u64> {
+    contents
+        .split_whitespace()
+        .next()
+        .and_then(|uptime| uptime.parse::<f64>().ok())
+        .map(|uptime| uptime as u64)
+        .ok_or_else(|| parse_error("uptime"))
+}
+
+fn read_millidegrees(path: &Path) -> Option<f64> {
+    let contents = fs::read_to_string(path).ok()?;
+    let millidegrees: i64 = contents.trim().parse().ok()?;
+    Some(millidegrees as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::PayloadValue;
+    use crate::test_util::TempDir;
+
+    const MEMINFO: &str = "\
+MemTotal:        4000000 kB
+MemFree:          500000 kB
+MemAvailable:    3000000 kB
+SwapTotal:       1000000 kB
+SwapFree:         750000 kB
+";
+
+    fn write_proc(dir: &Path, stat: &str) {
+        fs::write(dir.join("loadavg"), "0.52 0.39 0.40 1/72 20385\n").unwrap();
+        fs::write(dir.join("meminfo"), MEMINFO).unwrap();
+        fs::write(dir.join("stat"), stat).unwrap();
+        fs::write(dir.join("uptime"), "4329.16 2791.64\n").unwrap();
+    }
+
+    #[test]
+    fn parse_statistics() {
+        assert_eq!(
+            parse_loadavg("0.52 0.39 0.40 1/72 20385\n").unwrap(),
+            [0.52, 0.39, 0.40]
+        );
+        assert_eq!(
+            parse_cpu_times("cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 100 0 50 800 50 0 0 0 0 0\n")
+                .unwrap(),
+            CpuTimes {
+                total: 1000,
+                idle: 850
+            }
+        );
+        assert_eq!(
+            parse_meminfo(MEMINFO).unwrap(),
+            Memory {
+                total: 4096000000,
+                available: 3072000000,
+                swap_total: 1024000000,
+                swap_free: 768000000,
+            }
+        );
+        assert_eq!(parse_uptime("4329.96 2791.64\n").unwrap(), 4329);
+    }
+
+    #[test]
+    fn parse_invalid_statistics() {
+        assert_eq!(
+            parse_loadavg("0.52\n").unwrap_err().kind(),
+            ErrorKind::Parse
+        );
+        assert!(parse_cpu_times("intr 581322\n").is_err());
+        assert!(parse_meminfo("MemTotal: 4000000 kB\n").is_err());
+    }
+
+    #[test]
+    fn collect_system_statistics() {
+        let dir = TempDir::new("system");
+        fs::create_dir_all(dir.join("thermal/thermal_zone0")).unwrap();
+        fs::create_dir_all(dir.join("thermal/thermal_zone1")).unwrap();
+        fs::write(dir.join("thermal/thermal_zone0/temp"), "48312\n").unwrap();
+        fs::write(dir.join("thermal/thermal_zone1/temp"), "51000\n").unwrap();
+        write_proc(dir.path(), "cpu  100 0 50 800 50 0 0 0 0 0\n");
+        let mut system = System::new();
+        system.proc_dir = dir.path().to_path_buf();
+        system.thermal_dir = dir.join("thermal");
+
+        let msg = system.collect().unwrap().pop().unwrap();
+        assert_eq!(msg.source(), "system");
+        assert_eq!(msg.metrics()["load_1"], PayloadValue::Float64(0.52));
+        assert_eq!(
+            msg.metrics()["memory_used_percent"],
+            PayloadValue::Float64(25.0)
+        );
+        assert_eq!(msg.metrics()["swap_used"], PayloadValue::Uint64(256000000));
+        assert_eq!(msg.metrics()["uptime"], PayloadValue::Uint64(4329));
+        assert_eq!(msg.metrics()["temperature"], PayloadValue::Float64(51.0));
+        assert!(msg.metrics().get("cpu_usage_percent").is_none());
+
+        write_proc(dir.path(), "cpu  400 0 150 1300 150 0 0 0 0 0\n");
+        let msg = system.collect().unwrap().pop().unwrap();
+        assert_eq!(
+            msg.metrics()["cpu_usage_percent"],
+            PayloadValue::Float64(40.0)
+        );
+    }
+
+    #[test]
+    fn collect_without_thermal_zones() {
+        let mut system = System::new();
+        system.thermal_dir = PathBuf::from("/nonexistent");
+        let msg = system.collect().unwrap().pop().unwrap();
+
+        assert!(msg.metrics().get("temperature").is_none());
+        assert!(msg.metrics().get("uptime").is_some());
+    }
+}
@@ -0,0 +1,195 @@
+//! TCP collector opens a connection to a defined host and port and records
+//! how long the TCP handshake took in `connect_time` (milliseconds).
+//!
+//! When an expected banner is configured the first line which the server sends
+//! is read and reported in `banner` and `banner_matched` tells whether it
+//! started with the expected prefix. Servers which don't send a banner before
+//! the timeout don't match.
+use std::io::{BufRead, BufReader, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use super::{as_millis, Collector};
+use crate::config::Timeout;
+use crate::error::{Result, ResultError};
+use crate::message::Message;
+use crate::net::{self, IpFamily};
+use crate::url::HostPort;
+
+const MAX_BANNER_SIZE: u64 = 1024;
+
+pub struct Tcp {
+    address: HostPort,
+    family: Option<IpFamily>,
+    expect_banner: Option<String>,
+    timeout: Duration,
+}
+
+impl Tcp {
+    pub fn new(
+        address: HostPort,
+        family: Option<IpFamily>,
+        expect_banner: Option<String>,
+        timeout: Timeout,
+    ) -> Tcp {
+        Tcp {
+            address,
+            family,
+            expect_banner,
+            timeout: Duration::from_secs(timeout.into()),
+        }
+    }
+
+    fn connect(&self, addr: SocketAddr) -> Result<(TcpStream, Duration)> {
+        let start = Instant::now();
+        let stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        Ok((stream, start.elapsed()))
+    }
+
+    /// Reads the first line which the server sends.
+    fn read_banner(&self, stream: &TcpStream, connect_time: Duration) -> Result<String> {
+        // Zero timeout is not allowed
+        let timeout = self
+            .timeout
+            .saturating_sub(connect_time)
+            .max(Duration::from_millis(1));
+        stream.set_read_timeout(Some(timeout))?;
+        let mut banner = String::new();
+        BufReader::new(stream.take(MAX_BANNER_SIZE)).read_line(&mut banner)?;
+        Ok(banner.trim_end().to_string())
+    }
+}
+
+impl Collector for Tcp {
+    fn collect(&self) -> Result<Vec<Message>> {
+        let (addr, gateway) =
+            net::resolve_with_gateway(&self.address.host, self.family, self.address.port)
+                .set_source("tcp_collector")?;
+        let (stream, connect_time) = self.connect(addr).set_source("tcp_collector")?;
+
+        let mut message = self.new_message();
+        message.insert_tag("remote_ip", &addr.ip().to_string());
+        if let Some(gateway) = gateway {
+            message.insert_tag("gateway_ip", &gateway.addr.to_string());
+            message.insert_tag("gateway_interface", &gateway.interface);
+        }
+        message.insert_metric("connect_time", as_millis(connect_time));
+
+        if let Some(expected) = self.expect_banner.as_ref() {
+            let matched = match self.read_banner(&stream, connect_time) {
+                Ok(banner) => {
+                    let matched = banner.starts_with(expected.as_str());
+                    message.insert_metric("banner", banner);
+                    matched
+                }
+                Err(err) => {
+                    debug!("Failed to read banner from {}: {}", self.address, err);
+                    false
+                }
+            };
+            message.insert_metric("banner_matched", u8::from(matched));
+        }
+        Ok(vec![message])
+    }
+
+    fn new_message(&self) -> Message {
+        let mut message = Message::new("tcp");
+        message.insert_tag("host", &self.address.host.to_string());
+        message.insert_tag("port", &self.address.port.to_string());
+        if let Some(family) = self.family {
+            message.insert_tag("address_family", &family.to_string());
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use rstest::*;
+
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::message::PayloadValue;
+
+    /// Starts a server which accepts one connection and sends the banner.
+    fn start_server(banner: &'static str) -> HostPort {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(banner.as_bytes()).unwrap();
+            // Keep the connection open until the client closes it
+            let _ = stream.read(&mut [0; 1]);
+        });
+        addr.to_string().parse().unwrap()
+    }
+
+    #[test]
+    fn collect_connect_time() {
+        let address = start_server("");
+        let tcp = Tcp::new(address.clone(), None, None, Timeout(1));
+        let msg = tcp.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.source(), "tcp");
+        assert_eq!(msg.tags()["host"], "127.0.0.1");
+        assert_eq!(msg.tags()["port"], address.port.to_string());
+        assert_eq!(msg.tags()["remote_ip"], "127.0.0.1");
+        assert!(msg.metrics().get("connect_time").is_some());
+        assert!(msg.metrics().get("banner_matched").is_none());
+    }
+
+    #[rstest]
+    #[case("SSH-2.0-OpenSSH_9.6\r\n", "SSH-2.0", 1)]
+    #[case("220 mail.example.com ESMTP\r\n", "SSH-2.0", 0)]
+    fn collect_banner(#[case] banner: &'static str, #[case] expected: &str, #[case] matched: u8) {
+        let address = start_server(banner);
+        let tcp = Tcp::new(address, None, Some(expected.to_string()), Timeout(1));
+        let msg = tcp.collect().unwrap().pop().unwrap();
+
+        assert_eq!(
+            msg.metrics()["banner"],
+            PayloadValue::String(banner.trim_end().to_string())
+        );
+        assert_eq!(
+            msg.metrics()["banner_matched"],
+            PayloadValue::Uint8(matched)
+        );
+    }
+
+    #[test]
+    fn collect_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: HostPort = listener.local_addr().unwrap().to_string().parse().unwrap();
+        drop(listener);
+        let tcp = Tcp::new(address, None, None, Timeout(1));
+        let err = tcp.collect().unwrap_err();
+
+        assert_eq!(err.source().as_ref().unwrap(), "tcp_collector");
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[rstest]
+    #[case("example.com:22", "example.com", 22)]
+    #[case("[2001:db8::1]:5432", "[2001:db8::1]", 5432)]
+    #[case("@gateway:80", "@gateway", 80)]
+    fn parse_address(#[case] input: &str, #[case] host: &str, #[case] port: u16) {
+        let address: HostPort = input.parse().unwrap();
+
+        assert_eq!(address.host.to_string(), host);
+        assert_eq!(address.port, port);
+        assert_eq!(address.to_string(), input);
+    }
+
+    #[rstest]
+    #[case("example.com")]
+    #[case("example.com:ssh")]
+    fn parse_invalid_address(#[case] input: &str) {
+        assert!(input.parse::<HostPort>().is_err());
+    }
+}
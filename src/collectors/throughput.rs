@@ -0,0 +1,286 @@
+//! Throughput collector measures how fast data can be downloaded from or
+//! uploaded to a defined URL.
+//!
+//! Downloads are GET requests which read the response body until it ends or
+//! until the configured size or duration has been reached. Uploads are POST
+//! requests with a chunked payload of zeros which is sent until the
+//! configured size or duration has been reached. The collector timeout
+//! limits connecting and waiting for the response in addition to the transfer
+//! duration.
+//!
+//! Reported metrics are `bytes_downloaded` and `download_duration` or
+//! `bytes_uploaded` and `upload_duration` (in milliseconds) and
+//! `throughput_mbps` in megabits per second.
+use std::fmt;
+use std::time::Duration;
+
+use super::{as_millis, Collector};
+use crate::config::{HttpAuth, ThroughputOptions, Timeout};
+use crate::error::{Error, ErrorKind, Result, ResultError};
+use crate::message::Message;
+use crate::net::http::{Request, Response};
+use crate::url::HttpUrl;
+
+const BYTES_PER_MEGABYTE: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Download,
+    Upload,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Download => write!(f, "download"),
+            Direction::Upload => write!(f, "upload"),
+        }
+    }
+}
+
+pub struct Throughput {
+    url: HttpUrl,
+    direction: Direction,
+    options: ThroughputOptions,
+    timeout: Duration,
+}
+
+impl Throughput {
+    pub fn new(
+        url: HttpUrl,
+        direction: Direction,
+        options: ThroughputOptions,
+        timeout: Timeout,
+    ) -> Throughput {
+        Throughput {
+            url,
+            direction,
+            options,
+            timeout: Duration::from_secs(timeout.into()),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.options.size * BYTES_PER_MEGABYTE
+    }
+
+    fn send_request(&self, method: &str) -> Result<Response> {
+        let max_duration = Duration::from_secs(self.options.max_duration);
+        let mut request = Request::new(method, &self.url, self.timeout + max_duration);
+        for (name, value) in self.options.headers.iter() {
+            request = request.header(name, value);
+        }
+        match self.options.auth.as_ref() {
+            Some(HttpAuth::Basic { username, password }) => {
+                request = request.basic_auth(username, password.as_ref());
+            }
+            Some(HttpAuth::Bearer { token }) => request = request.bearer_auth(token.as_ref()),
+            None => (),
+        }
+        let max_duration = Some(max_duration);
+        let range;
+        match self.direction {
+            Direction::Download if self.options.range => {
+                range = format!("bytes=0-{}", self.size() - 1);
+                request = request.header("Range", &range);
+            }
+            Direction::Download => (),
+            Direction::Upload => {
+                request = request
+                    .header("Content-Type", "application/octet-stream")
+                    .zero_body(self.size(), max_duration);
+            }
+        }
+
+        let resp = request
+            .receive_limits(self.size(), max_duration)
+            .discard_body()
+            .send()?;
+        if !(200..300).contains(&resp.status) {
+            return Err(Error::new("Unsuccessful HTTP status code")
+                .set_kind(ErrorKind::HttpStatus)
+                .set_context(&resp.status.to_string()));
+        }
+        Ok(resp)
+    }
+}
+
+impl Collector for Throughput {
+    fn collect(&self) -> Result<Vec<Message>> {
+        let (bytes, duration, resp) = match self.direction {
+            Direction::Download => {
+                let resp = self
+                    .send_request("GET")
+                    .set_source("throughput_collector")?;
+                (resp.body_size, resp.timings.receive, resp)
+            }
+            Direction::Upload => {
+                let resp = self
+                    .send_request("POST")
+                    .set_source("throughput_collector")?;
+                // The server may respond only after it has received the
+                // whole payload so the wait is part of the upload.
+                (
+                    resp.sent_body_size,
+                    resp.timings.send + resp.timings.ttfb,
+                    resp,
+                )
+            }
+        };
+
+        let mut message = self.new_message();
+        message.insert_metric("status_code", resp.status);
+        let (bytes_metric, duration_metric) = match self.direction {
+            Direction::Download => ("bytes_downloaded", "download_duration"),
+            Direction::Upload => ("bytes_uploaded", "upload_duration"),
+        };
+        message.insert_metric(bytes_metric, bytes);
+        message.insert_metric(duration_metric, as_millis(duration));
+        if !duration.is_zero() {
+            let megabits = (bytes * 8) as f64 / BYTES_PER_MEGABYTE as f64;
+            message.insert_metric("throughput_mbps", megabits / duration.as_secs_f64());
+        }
+        Ok(vec![message])
+    }
+
+    fn new_message(&self) -> Message {
+        let mut message = Message::new("throughput");
+        message.insert_tag("url", self.url.as_str());
+        message.insert_tag("direction", &self.direction.to_string());
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate mockito;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::message::PayloadValue;
+
+    fn options(size: u64) -> ThroughputOptions {
+        ThroughputOptions {
+            size,
+            ..ThroughputOptions::default()
+        }
+    }
+
+    #[test]
+    fn collect_download() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("GET", "/file.bin")
+            .with_body(vec![1; 300_000])
+            .create();
+        let url: HttpUrl = format!("{}/file.bin", server.url()).parse().unwrap();
+        let throughput = Throughput::new(url.clone(), Direction::Download, options(1), Timeout(5));
+        let msg = throughput.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.source(), "throughput");
+        assert_eq!(msg.tags()["url"], url.to_string());
+        assert_eq!(msg.tags()["direction"], "download");
+        assert_eq!(
+            msg.metrics()["bytes_downloaded"],
+            PayloadValue::Uint64(300_000)
+        );
+        assert!(msg.metrics().get("download_duration").is_some());
+        assert!(msg.metrics().get("throughput_mbps").is_some());
+        m.assert();
+    }
+
+    #[test]
+    fn collect_download_with_size_limit() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("GET", "/")
+            .match_header("range", "bytes=0-999999")
+            .with_body(vec![1; 1_500_000])
+            .create();
+        let url: HttpUrl = server.url().parse().unwrap();
+        let options = ThroughputOptions {
+            range: true,
+            ..options(1)
+        };
+        let throughput = Throughput::new(url, Direction::Download, options, Timeout(5));
+        let msg = throughput.collect().unwrap().pop().unwrap();
+
+        assert_eq!(
+            msg.metrics()["bytes_downloaded"],
+            PayloadValue::Uint64(1_000_000)
+        );
+        m.assert();
+    }
+
+    #[test]
+    fn collect_upload() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("POST", "/upload")
+            .match_header("content-type", "application/octet-stream")
+            .match_header("transfer-encoding", "chunked")
+            .with_status(204)
+            .create();
+        let url: HttpUrl = format!("{}/upload", server.url()).parse().unwrap();
+        let throughput = Throughput::new(url, Direction::Upload, options(2), Timeout(5));
+        let msg = throughput.collect().unwrap().pop().unwrap();
+
+        assert_eq!(msg.tags()["direction"], "upload");
+        assert_eq!(msg.metrics()["status_code"], PayloadValue::Uint16(204));
+        assert_eq!(
+            msg.metrics()["bytes_uploaded"],
+            PayloadValue::Uint64(2_000_000)
+        );
+        assert!(msg.metrics().get("upload_duration").is_some());
+        m.assert();
+    }
+
+    #[test]
+    fn download_stops_at_max_duration() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: HttpUrl = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            while stream.write_all(&[1; 1000]).is_ok() {
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        // The transfer takes longer than the timeout
+        let options = ThroughputOptions {
+            max_duration: 2,
+            ..options(1)
+        };
+        let throughput = Throughput::new(url, Direction::Download, options, Timeout(1));
+        let start = Instant::now();
+        let msg = throughput.collect().unwrap().pop().unwrap();
+
+        let duration = msg.metrics()["download_duration"].as_f64().unwrap();
+        assert!((2000.0..2500.0).contains(&duration));
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn collect_unsuccessful_status() {
+        let mut server = mockito::Server::new();
+        let m = server.mock("GET", "/").with_status(404).create();
+        let url: HttpUrl = server.url().parse().unwrap();
+        let throughput = Throughput::new(url, Direction::Download, options(1), Timeout(5));
+        let err = throughput.collect().unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::HttpStatus);
+        m.assert();
+    }
+}
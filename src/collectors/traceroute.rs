@@ -0,0 +1,254 @@
+//! Traceroute collector sends probes with increasing hop limit to a defined
+//! host. Probes to a hop are sent at once and the trace stops when the
+//! collector timeout is reached. Every hop is reported in its own
+//! `traceroute_hop` message with the responding address, packet loss and
+//! round-trip-time statistics.
+//!
+//! Summary is reported in a `traceroute` message which tells how many hops the
+//! path had, whether the destination was reached and whether the path changed
+//! since the previous run. Path is compared by the first responding address of
+//! every hop. Hops which didn't respond are not compared.
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use super::ping::PingStatistics;
+use super::Collector;
+use crate::config::{Timeout, TracerouteProtocol};
+use crate::error::{Result, ResultError};
+use crate::message::Message;
+use crate::net::traceroute::{ProbeProtocol, ReplyKind, Tracer};
+use crate::net::{self, IpFamily};
+use crate::url::Host;
+
+pub struct Traceroute {
+    host: Host,
+    family: Option<IpFamily>,
+    protocol: ProbeProtocol,
+    max_hops: u8,
+    probes: u16,
+    probe_timeout: Duration,
+    timeout: Duration,
+    /// Responding addresses of the hops on the previous runs.
+    previous_path: Mutex<Option<Vec<Option<IpAddr>>>>,
+}
+
+/// Replies to the probes which were sent to one hop.
+struct Hop {
+    number: u8,
+    address: Option<IpAddr>,
+    stats: PingStatistics,
+}
+
+impl Traceroute {
+    pub fn new(
+        host: Host,
+        family: Option<IpFamily>,
+        protocol: TracerouteProtocol,
+        max_hops: u8,
+        probes: u16,
+        probe_timeout: u64,
+        timeout: Timeout,
+    ) -> Traceroute {
+        let protocol = match protocol {
+            TracerouteProtocol::Udp => ProbeProtocol::Udp,
+            TracerouteProtocol::Icmp => ProbeProtocol::Icmp,
+        };
+        Traceroute {
+            host,
+            family,
+            protocol,
+            max_hops,
+            probes,
+            probe_timeout: Duration::from_millis(probe_timeout),
+            timeout: Duration::from_secs(timeout.into()),
+            previous_path: Mutex::new(None),
+        }
+    }
+
+    /// Probes hops until the destination or an unreachable reply is received
+    /// or the maximum number of hops or the timeout is reached. Returns the
+    /// hops and whether the destination was reached.
+    fn trace(&self, addr: SocketAddr) -> Result<(Vec<Hop>, bool)> {
+        let tracer = Tracer::new(addr, self.protocol)?;
+        let deadline = Instant::now() + self.timeout;
+
+        let mut hops = Vec::new();
+        let mut sequence: u16 = 0;
+        for number in 1..=self.max_hops {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                debug!("Trace to {} timed out at hop {}", self.host, number);
+                break;
+            }
+            let replies = tracer.probe(
+                number,
+                sequence,
+                self.probes,
+                self.probe_timeout.min(remaining),
+            )?;
+            sequence = sequence.wrapping_add(self.probes);
+
+            let rtts: Vec<_> = replies.iter().map(|reply| reply.rtt).collect();
+            hops.push(Hop {
+                number,
+                address: replies.first().map(|reply| reply.from),
+                stats: PingStatistics::new(self.probes, &rtts),
+            });
+            let replied = |kind| replies.iter().any(|reply| reply.kind == kind);
+            if replied(ReplyKind::Destination) {
+                return Ok((hops, true));
+            }
+            if replied(ReplyKind::Unreachable) {
+                return Ok((hops, false));
+            }
+        }
+        Ok((hops, false))
+    }
+
+    fn hop_message(&self, hop: &Hop) -> Message {
+        let mut message = Message::new("traceroute_hop");
+        message.insert_tag("host", &self.host.to_string());
+        message.insert_tag("hop", &hop.number.to_string());
+        if let Some(address) = hop.address {
+            message.insert_tag("hop_address", &address.to_string());
+        }
+        if let Some(family) = self.family {
+            message.insert_tag("address_family", &family.to_string());
+        }
+        message.insert_metric("hop", hop.number);
+        message.insert_metric("probes_sent", hop.stats.packets_sent);
+        message.insert_metric("probes_received", hop.stats.packets_received);
+        message.insert_metric("packet_loss_percent", hop.stats.packet_loss_percent());
+        if let Some(rtt) = hop.stats.rtt.as_ref() {
+            message.insert_metric("rtt_min", rtt.min);
+            message.insert_metric("rtt_avg", rtt.avg);
+            message.insert_metric("rtt_max", rtt.max);
+        }
+        message
+    }
+
+    /// Stores the path and tells whether it differs from the previous one.
+    /// Hops which didn't respond on either run are not compared and the
+    /// previous address is kept for them. First run doesn't have a path to
+    /// compare with and isn't a change.
+    fn update_path(&self, mut path: Vec<Option<IpAddr>>) -> bool {
+        let mut previous = self.previous_path.lock().unwrap();
+        let Some(previous) = previous.as_mut() else {
+            *previous = Some(path);
+            return false;
+        };
+        let mut changed = false;
+        for (hop, address) in path.iter_mut().enumerate() {
+            match (previous.get(hop).copied().flatten(), *address) {
+                (Some(before), Some(now)) => changed |= before != now,
+                (Some(before), None) => *address = Some(before),
+                _ => (),
+            }
+        }
+        *previous = path;
+        changed
+    }
+}
+
+impl Collector for Traceroute {
+    fn collect(&self) -> Result<Vec<Message>> {
+        let (addr, gateway) = net::resolve_with_gateway(&self.host, self.family, 0)
+            .set_source("traceroute_collector")?;
+        let (hops, reached) = self.trace(addr).set_source("traceroute_collector")?;
+
+        let mut messages: Vec<Message> = hops.iter().map(|hop| self.hop_message(hop)).collect();
+        let changed = self.update_path(hops.iter().map(|hop| hop.address).collect());
+
+        let mut message = self.new_message();
+        message.insert_tag("remote_ip", &addr.ip().to_string());
+        if let Some(gateway) = gateway {
+            message.insert_tag("gateway_ip", &gateway.addr.to_string());
+            message.insert_tag("gateway_interface", &gateway.interface);
+        }
+        message.insert_metric("hop_count", hops.len() as u8);
+        message.insert_metric("destination_reached", u8::from(reached));
+        message.insert_metric("path_changed", u8::from(changed));
+        messages.push(message);
+        Ok(messages)
+    }
+
+    fn new_message(&self) -> Message {
+        let mut message = Message::new("traceroute");
+        message.insert_tag("host", &self.host.to_string());
+        if let Some(family) = self.family {
+            message.insert_tag("address_family", &family.to_string());
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::PayloadValue;
+
+    fn traceroute() -> Traceroute {
+        Traceroute::new(
+            "localhost".parse().unwrap(),
+            None,
+            TracerouteProtocol::Udp,
+            5,
+            2,
+            100,
+            Timeout(1),
+        )
+    }
+
+    #[test]
+    fn path_changes() {
+        let traceroute = traceroute();
+        let a = Some("192.0.2.1".parse().unwrap());
+        let b = Some("192.0.2.2".parse().unwrap());
+
+        let c = Some("192.0.2.3".parse().unwrap());
+
+        assert!(!traceroute.update_path(vec![a, None]));
+        assert!(!traceroute.update_path(vec![a, None]));
+        assert!(!traceroute.update_path(vec![None, c]));
+        assert!(!traceroute.update_path(vec![a, None]));
+        assert!(traceroute.update_path(vec![b, c]));
+        assert!(traceroute.update_path(vec![b, a, c]));
+    }
+
+    #[test]
+    fn hop_message_without_replies() {
+        let hop = Hop {
+            number: 3,
+            address: None,
+            stats: PingStatistics::new(2, &[]),
+        };
+        let msg = traceroute().hop_message(&hop);
+
+        assert_eq!(msg.source(), "traceroute_hop");
+        assert_eq!(msg.tags()["hop"], "3");
+        assert!(msg.tags().get("hop_address").is_none());
+        assert_eq!(msg.metrics()["probes_received"], PayloadValue::Uint16(0));
+        assert!(msg.metrics().get("rtt_avg").is_none());
+    }
+
+    #[test]
+    #[ignore]
+    fn traceroute_collect() {
+        let mut messages = traceroute().collect().unwrap();
+        let summary = messages.pop().unwrap();
+
+        assert_eq!(summary.source(), "traceroute");
+        assert_eq!(summary.metrics()["hop_count"], PayloadValue::Uint8(1));
+        assert_eq!(
+            summary.metrics()["destination_reached"],
+            PayloadValue::Uint8(1)
+        );
+        assert_eq!(
+            messages[0].tags()["hop_address"],
+            summary.tags()["remote_ip"]
+        );
+    }
+}
@@ -37,7 +37,7 @@ impl Collector for Wireless {
         let mut messages = Vec::new();
         for interface in interfaces {
             log::debug!("Found interface: {:?}", interface);
-            let mut message = Message::new("wireless_interface");
+            let mut message = self.new_message();
             if interface.name.is_empty() {
                 log::debug!("Skipping wireless interface with empty name");
                 continue;
@@ -114,6 +114,10 @@ impl Collector for Wireless {
         }
         Ok(messages)
     }
+
+    fn new_message(&self) -> Message {
+        Message::new("wireless_interface")
+    }
 }
 
 fn channel_width_to_number(channel_width: &ChannelWidth) -> Option<u32> {
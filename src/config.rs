@@ -1,14 +1,18 @@
 //! Uption configuration.
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
-use std::net::Ipv4Addr;
-use std::path::Path;
+use std::net::{AddrParseError, IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use crate::url::{Host, HttpUrl};
+use crate::url::{Host, HostPort, HttpUrl};
 use config::{Config, ConfigError, Environment, File};
 use log::LevelFilter;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct UptionConfig {
     pub general: GeneralConfig,
@@ -27,7 +31,7 @@ impl Validate for UptionConfig {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct GeneralConfig {
     pub hostname: String,
@@ -52,7 +56,7 @@ impl Default for GeneralConfig {
     }
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 #[serde(remote = "LevelFilter")]
 #[serde(rename = "log_level")]
@@ -65,7 +69,7 @@ pub enum LevelFilterDef {
     Trace,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct LoggerConfig {
     #[serde(with = "LevelFilterDef")]
@@ -94,26 +98,38 @@ impl Default for LoggerConfig {
         }
     }
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct CollectorsConfig {
+    /// Default collection interval in seconds for collectors and targets which
+    /// don't set their own interval.
     pub interval: u64,
     pub dns: DnsConfig,
     pub http: HttpConfig,
+    pub netdev: NetdevConfig,
+    pub netstat: NetstatConfig,
+    pub ntp: NtpConfig,
     pub ping: PingConfig,
+    pub system: SystemConfig,
+    pub tcp: TcpConfig,
+    pub throughput: ThroughputConfig,
+    pub traceroute: TracerouteConfig,
     pub wireless: WirelessConfig,
 }
 
 impl Validate for CollectorsConfig {
     fn validate(&self) -> Result<(), ConfigError> {
-        if self.interval < 1 || self.interval > 86400 {
-            return Err(ConfigError::Message(
-                "collectors.interval minimum value is 1 and maximum value is 86400".to_string(),
-            ));
-        }
+        validate_interval("collectors.interval", Some(self.interval))?;
         self.dns.validate()?;
         self.http.validate()?;
+        self.netdev.validate()?;
+        self.netstat.validate()?;
+        self.ntp.validate()?;
         self.ping.validate()?;
+        self.system.validate()?;
+        self.tcp.validate()?;
+        self.throughput.validate()?;
+        self.traceroute.validate()?;
         self.wireless.validate()?;
         Ok(())
     }
@@ -125,42 +141,367 @@ impl Default for CollectorsConfig {
             interval: 300, // 5 minutes
             dns: DnsConfig::default(),
             http: HttpConfig::default(),
+            netdev: NetdevConfig::default(),
+            netstat: NetstatConfig::default(),
+            ntp: NtpConfig::default(),
             ping: PingConfig::default(),
+            system: SystemConfig::default(),
+            tcp: TcpConfig::default(),
+            throughput: ThroughputConfig::default(),
+            traceroute: TracerouteConfig::default(),
             wireless: WirelessConfig::default(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct DnsConfig {
     pub enabled: bool,
-    pub dns_servers: Vec<Ipv4Addr>,
-    pub hosts: Vec<Host>,
+    pub interval: Option<u64>,
+    pub dns_servers: Vec<DnsServer>,
+    pub hosts: Vec<DnsTarget>,
     pub timeout: Timeout,
+    /// Queries only the DNS servers of this address family.
+    pub address_family: AddressFamily,
+    /// Queries also the nameservers of the system resolver configuration.
+    pub system_dns_servers: bool,
 }
 
 impl Validate for DnsConfig {
     fn validate(&self) -> Result<(), ConfigError> {
         if self.enabled {
-            if self.dns_servers.is_empty() {
+            if self.dns_servers.is_empty() && !self.system_dns_servers {
                 return Err(ConfigError::Message(
-                    "dns.dns_servers can't be empty".to_string(),
+                    "dns.dns_servers can't be empty unless dns.system_dns_servers is enabled"
+                        .to_string(),
                 ));
             } else if self.hosts.is_empty() {
                 return Err(ConfigError::Message("dns.hosts can't be empty".to_string()));
+            } else if !self.dns_servers.is_empty()
+                && !self.dns_servers.iter().any(|server| {
+                    server
+                        .ip()
+                        .is_none_or(|ip| self.address_family.includes(&ip))
+                })
+            {
+                return Err(ConfigError::Message(
+                    "dns.dns_servers has no servers of dns.address_family".to_string(),
+                ));
+            }
+        }
+        validate_interval("dns.interval", self.interval)?;
+        for host in self.hosts.iter() {
+            if matches!(host.host, Host::Gateway) {
+                return Err(ConfigError::Message(
+                    "dns.hosts can't be @gateway".to_string(),
+                ));
             }
+            validate_interval("dns.hosts.interval", host.interval)?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Default)]
+/// DNS server and the transport which is used to query it, e.g. `1.1.1.1`,
+/// `[2001:db8::53]:5353`, `tcp://1.1.1.1`, `tls://1.1.1.1`,
+/// `tls://dns.example.com`, `tls://dns.example.com@192.0.2.53` or
+/// `https://cloudflare-dns.com/dns-query`. Port is 53 for UDP and TCP and 853
+/// for TLS unless it's set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DnsServer {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Tls(TlsDnsServer),
+    Https(HttpUrl),
+}
+
+/// DNS-over-TLS server. Server certificate is verified against the name. The
+/// name is resolved at every query unless the address is set.
+#[derive(Debug, Clone)]
+pub struct TlsDnsServer {
+    /// Domain name or the IP address of the server.
+    pub name: Host,
+    pub addr: Option<IpAddr>,
+    pub port: u16,
+}
+
+impl DnsServer {
+    pub fn transport(&self) -> &'static str {
+        match self {
+            DnsServer::Udp(_) => "udp",
+            DnsServer::Tcp(_) => "tcp",
+            DnsServer::Tls(_) => "tls",
+            DnsServer::Https(_) => "https",
+        }
+    }
+
+    /// Returns the IP address of the server. DNS-over-HTTPS servers which are
+    /// defined with a domain name don't have a fixed address.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            DnsServer::Udp(addr) | DnsServer::Tcp(addr) => Some(addr.ip()),
+            DnsServer::Tls(server) => server.addr,
+            DnsServer::Https(url) => match url.host() {
+                Host::Host(url::Host::Ipv4(addr)) => Some(IpAddr::V4(addr)),
+                Host::Host(url::Host::Ipv6(addr)) => Some(IpAddr::V6(addr)),
+                Host::Host(url::Host::Domain(_)) | Host::Gateway => None,
+            },
+        }
+    }
+}
+
+impl FromStr for DnsServer {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let socket_addr = |addr: &str, default_port| {
+            addr.parse()
+                .or_else(|_| {
+                    // IPv6 addresses may be in brackets also without a port
+                    let ip = addr
+                        .strip_prefix('[')
+                        .and_then(|addr| addr.strip_suffix(']'))
+                        .unwrap_or(addr);
+                    ip.parse()
+                        .map(|ip: IpAddr| SocketAddr::new(ip, default_port))
+                })
+                .map_err(|e: AddrParseError| format!("invalid DNS server {}: {}", input, e))
+        };
+
+        if input.starts_with("https://") {
+            let url = input
+                .parse()
+                .map_err(|e| format!("invalid DNS server {}: {}", input, e))?;
+            Ok(DnsServer::Https(url))
+        } else if let Some(server) = input.strip_prefix("tls://") {
+            let domain = |name: &str| match Host::parse(name) {
+                Ok(host @ Host::Host(url::Host::Domain(_))) => Ok(host),
+                _ => Err(format!("invalid DNS server name in {}", input)),
+            };
+            let server = match server.split_once('@') {
+                Some((name, addr)) => {
+                    let addr = socket_addr(addr, 853)?;
+                    TlsDnsServer {
+                        name: domain(name)?,
+                        addr: Some(addr.ip()),
+                        port: addr.port(),
+                    }
+                }
+                None => match socket_addr(server, 853) {
+                    Ok(addr) => TlsDnsServer {
+                        name: Host::from(addr.ip()),
+                        addr: Some(addr.ip()),
+                        port: addr.port(),
+                    },
+                    Err(_) => {
+                        let (name, port) = match server.rsplit_once(':') {
+                            Some((name, port)) => (
+                                name,
+                                port.parse().map_err(|e| {
+                                    format!("invalid port in DNS server {}: {}", input, e)
+                                })?,
+                            ),
+                            None => (server, 853),
+                        };
+                        TlsDnsServer {
+                            name: domain(name)?,
+                            addr: None,
+                            port,
+                        }
+                    }
+                },
+            };
+            Ok(DnsServer::Tls(server))
+        } else if let Some(addr) = input.strip_prefix("tcp://") {
+            socket_addr(addr, 53).map(DnsServer::Tcp)
+        } else {
+            socket_addr(input.strip_prefix("udp://").unwrap_or(input), 53).map(DnsServer::Udp)
+        }
+    }
+}
+
+impl TryFrom<String> for DnsServer {
+    type Error = String;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+impl From<DnsServer> for String {
+    fn from(server: DnsServer) -> Self {
+        server.to_string()
+    }
+}
+
+impl fmt::Display for DnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_addr = |f: &mut fmt::Formatter<'_>, addr: &SocketAddr, default_port| {
+            if addr.port() == default_port {
+                write!(f, "{}", addr.ip())
+            } else {
+                write!(f, "{}", addr)
+            }
+        };
+        match self {
+            DnsServer::Udp(addr) => write_addr(f, addr, 53),
+            DnsServer::Tcp(addr) => {
+                write!(f, "tcp://")?;
+                write_addr(f, addr, 53)
+            }
+            DnsServer::Tls(server) => {
+                write!(f, "tls://")?;
+                match (&server.name, server.addr) {
+                    (Host::Host(url::Host::Domain(name)), Some(addr)) => {
+                        write!(f, "{}@", name)?;
+                        write_addr(f, &SocketAddr::new(addr, server.port), 853)
+                    }
+                    (_, Some(addr)) => write_addr(f, &SocketAddr::new(addr, server.port), 853),
+                    (name, None) if server.port == 853 => write!(f, "{}", name),
+                    (name, None) => write!(f, "{}:{}", name, server.port),
+                }
+            }
+            DnsServer::Https(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Defines a collection target which can be configured with a plain value,
+/// e.g. a host, or with a table which has the value, `interval` and target
+/// specific options. Options are flattened into the table and the plain
+/// value has the default options.
+macro_rules! collection_target {
+    (
+        $(#[$attr:meta])*
+        $name:ident($def:ident) { $key:ident: $key_type:ty, options: $options:ty }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone)]
+        pub struct $name {
+            pub $key: $key_type,
+            pub interval: Option<u64>,
+            pub options: $options,
+        }
+
+        #[derive(Serialize, Deserialize)]
+        #[serde(untagged)]
+        enum $def {
+            Plain($key_type),
+            Table {
+                $key: $key_type,
+                interval: Option<u64>,
+                #[serde(flatten)]
+                options: Box<$options>,
+            },
+        }
+
+        impl From<$name> for $def {
+            fn from(target: $name) -> Self {
+                if target.interval.is_none() && target.options == <$options>::default() {
+                    return $def::Plain(target.$key);
+                }
+                $def::Table {
+                    $key: target.$key,
+                    interval: target.interval,
+                    options: Box::new(target.options),
+                }
+            }
+        }
+
+        impl From<$def> for $name {
+            fn from(target: $def) -> Self {
+                match target {
+                    $def::Plain($key) => $name {
+                        $key,
+                        interval: None,
+                        options: <$options>::default(),
+                    },
+                    $def::Table {
+                        $key,
+                        interval,
+                        options,
+                    } => $name {
+                        $key,
+                        interval,
+                        options: *options,
+                    },
+                }
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                $def::from(self.clone()).serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                $def::deserialize(deserializer).map($name::from)
+            }
+        }
+    };
+}
+
+collection_target! {
+    /// DNS query target. Targets can be configured with a plain host which is
+    /// queried for A records or with a table which has target specific options,
+    /// e.g. `{ host = "example.com", record_type = "MX", expected = ["10 mx.example.com"] }`.
+    DnsTarget(DnsTargetDef) { host: Host, options: DnsOptions }
+}
+
+/// Query options of a DNS target.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DnsOptions {
+    pub record_type: DnsRecordType,
+    /// Values which the answer records are expected to have. The answer
+    /// matches when it has exactly these values in any order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub expected: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DnsRecordType {
+    #[default]
+    A,
+    Aaaa,
+    Mx,
+    Txt,
+    Cname,
+    Ns,
+    Soa,
+    Ptr,
+    Srv,
+}
+
+impl DnsRecordType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DnsRecordType::A => "A",
+            DnsRecordType::Aaaa => "AAAA",
+            DnsRecordType::Mx => "MX",
+            DnsRecordType::Txt => "TXT",
+            DnsRecordType::Cname => "CNAME",
+            DnsRecordType::Ns => "NS",
+            DnsRecordType::Soa => "SOA",
+            DnsRecordType::Ptr => "PTR",
+            DnsRecordType::Srv => "SRV",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct HttpConfig {
     pub enabled: bool,
-    pub urls: Vec<HttpUrl>,
+    pub interval: Option<u64>,
+    pub urls: Vec<UrlTarget>,
     pub timeout: Timeout,
+    pub address_family: AddressFamily,
 }
 
 impl Validate for HttpConfig {
@@ -168,16 +509,99 @@ impl Validate for HttpConfig {
         if self.enabled && self.urls.is_empty() {
             return Err(ConfigError::Message("http.urls can't be empty".to_string()));
         }
+        validate_interval("http.interval", self.interval)?;
+        for url in self.urls.iter() {
+            url.validate()?;
+        }
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct NetdevConfig {
+    pub enabled: bool,
+    pub interval: Option<u64>,
+    /// Names of the reported interfaces. All interfaces are reported when
+    /// this is empty.
+    pub interfaces: Vec<String>,
+}
+
+impl Validate for NetdevConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        validate_interval("netdev.interval", self.interval)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct NetstatConfig {
+    pub enabled: bool,
+    pub interval: Option<u64>,
+}
+
+impl Validate for NetstatConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        validate_interval("netstat.interval", self.interval)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct NtpConfig {
+    pub enabled: bool,
+    pub interval: Option<u64>,
+    pub servers: Vec<HostTarget>,
+    pub timeout: Timeout,
+    pub address_family: AddressFamily,
+    /// Maximum offset of the local clock in milliseconds. Collected messages
+    /// are handled with `max_offset_action` while the offset is larger.
+    pub max_offset: Option<u64>,
+    pub max_offset_action: ClockGuardAction,
+}
+
+impl Validate for NtpConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.enabled && self.servers.is_empty() {
+            return Err(ConfigError::Message(
+                "ntp.servers can't be empty".to_string(),
+            ));
+        }
+        if self.max_offset == Some(0) {
+            return Err(ConfigError::Message(
+                "ntp.max_offset minimum value is 1".to_string(),
+            ));
+        }
+        validate_interval("ntp.interval", self.interval)?;
+        for server in self.servers.iter() {
+            validate_interval("ntp.servers.interval", server.interval)?;
+        }
+        Ok(())
+    }
+}
+
+/// Handling of collected messages while the local clock offset is over the
+/// limit. `flag` adds a `clock_offset_exceeded` tag and `drop` doesn't export
+/// the messages.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockGuardAction {
+    #[default]
+    Flag,
+    Drop,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct PingConfig {
     pub enabled: bool,
-    pub hosts: Vec<Host>,
+    pub interval: Option<u64>,
+    pub hosts: Vec<HostTarget>,
     pub timeout: Timeout,
+    pub packet_count: u16,
+    /// Interval between sent packets in milliseconds.
+    pub packet_interval: u64,
+    pub address_family: AddressFamily,
 }
 
 impl Validate for PingConfig {
@@ -187,60 +611,614 @@ impl Validate for PingConfig {
                 "ping.hosts can't be empty".to_string(),
             ));
         }
+        if self.packet_count < 1 || self.packet_count > 1000 {
+            return Err(ConfigError::Message(
+                "ping.packet_count minimum value is 1 and maximum value is 1000".to_string(),
+            ));
+        }
+        if self.packet_interval < 10 || self.packet_interval > 60000 {
+            return Err(ConfigError::Message(
+                "ping.packet_interval minimum value is 10 and maximum value is 60000".to_string(),
+            ));
+        }
+        validate_interval("ping.interval", self.interval)?;
+        for host in self.hosts.iter() {
+            validate_interval("ping.hosts.interval", host.interval)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for PingConfig {
+    fn default() -> Self {
+        PingConfig {
+            enabled: false,
+            interval: None,
+            hosts: Vec::new(),
+            timeout: Timeout::default(),
+            packet_count: 1,
+            packet_interval: 1000, // 1 second
+            address_family: AddressFamily::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TcpConfig {
+    pub enabled: bool,
+    pub interval: Option<u64>,
+    pub targets: Vec<TcpTarget>,
+    pub timeout: Timeout,
+    pub address_family: AddressFamily,
+}
+
+impl Validate for TcpConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.enabled && self.targets.is_empty() {
+            return Err(ConfigError::Message(
+                "tcp.targets can't be empty".to_string(),
+            ));
+        }
+        validate_interval("tcp.interval", self.interval)?;
+        for target in self.targets.iter() {
+            validate_interval("tcp.targets.interval", target.interval)?;
+        }
+        Ok(())
+    }
+}
+
+collection_target! {
+    /// TCP connection target. Targets can be configured with a plain `host:port`
+    /// or with a table which has target specific options, e.g.
+    /// `{ address = "example.com:22", expect_banner = "SSH-2.0" }`.
+    TcpTarget(TcpTargetDef) { address: HostPort, options: TcpOptions }
+}
+
+/// Connection options of a TCP target.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct TcpOptions {
+    /// Expected prefix of the banner which the server sends after the
+    /// connection has been established. Banner is read only when this is set.
+    pub expect_banner: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ThroughputConfig {
+    pub enabled: bool,
+    pub interval: Option<u64>,
+    pub downloads: Vec<ThroughputTarget>,
+    pub uploads: Vec<ThroughputTarget>,
+    pub timeout: Timeout,
+}
+
+impl Validate for ThroughputConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.enabled && self.downloads.is_empty() && self.uploads.is_empty() {
+            return Err(ConfigError::Message(
+                "throughput.downloads and throughput.uploads can't both be empty".to_string(),
+            ));
+        }
+        validate_interval("throughput.interval", self.interval)?;
+        for target in self.downloads.iter().chain(self.uploads.iter()) {
+            target.validate()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SystemConfig {
+    pub enabled: bool,
+    pub interval: Option<u64>,
+}
+
+impl Validate for SystemConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        validate_interval("system.interval", self.interval)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TracerouteConfig {
+    pub enabled: bool,
+    pub interval: Option<u64>,
+    pub hosts: Vec<HostTarget>,
+    pub protocol: TracerouteProtocol,
+    /// Maximum number of hops to the destination.
+    pub max_hops: u8,
+    /// Number of probes which are sent to each hop.
+    pub probes: u16,
+    /// Time to wait for the replies to the probes of a hop in milliseconds.
+    pub probe_timeout: u64,
+    /// Maximum duration of a trace in seconds.
+    pub timeout: Timeout,
+    pub address_family: AddressFamily,
+}
+
+impl Validate for TracerouteConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.enabled && self.hosts.is_empty() {
+            return Err(ConfigError::Message(
+                "traceroute.hosts can't be empty".to_string(),
+            ));
+        }
+        if self.max_hops < 1 || self.max_hops > 64 {
+            return Err(ConfigError::Message(
+                "traceroute.max_hops minimum value is 1 and maximum value is 64".to_string(),
+            ));
+        }
+        if self.probes < 1 || self.probes > 10 {
+            return Err(ConfigError::Message(
+                "traceroute.probes minimum value is 1 and maximum value is 10".to_string(),
+            ));
+        }
+        if self.probe_timeout < 10 || self.probe_timeout > 10000 {
+            return Err(ConfigError::Message(
+                "traceroute.probe_timeout minimum value is 10 and maximum value is 10000"
+                    .to_string(),
+            ));
+        }
+        if self.probe_timeout > u64::from(self.timeout) * 1000 {
+            return Err(ConfigError::Message(
+                "traceroute.probe_timeout can't be longer than traceroute.timeout".to_string(),
+            ));
+        }
+        validate_interval("traceroute.interval", self.interval)?;
+        for host in self.hosts.iter() {
+            validate_interval("traceroute.hosts.interval", host.interval)?;
+        }
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Default)]
+impl Default for TracerouteConfig {
+    fn default() -> Self {
+        TracerouteConfig {
+            enabled: false,
+            interval: None,
+            hosts: Vec::new(),
+            protocol: TracerouteProtocol::default(),
+            max_hops: 30,
+            probes: 3,
+            probe_timeout: 1000, // 1 second
+            timeout: Timeout::default(),
+            address_family: AddressFamily::default(),
+        }
+    }
+}
+
+/// Protocol of the traceroute probes. UDP probes are sent to high ports which
+/// the destination is expected to reject and ICMP probes are echo requests.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TracerouteProtocol {
+    #[default]
+    Udp,
+    Icmp,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct WirelessConfig {
     pub enabled: bool,
+    pub interval: Option<u64>,
 }
 
 impl Validate for WirelessConfig {
     fn validate(&self) -> Result<(), ConfigError> {
+        validate_interval("wireless.interval", self.interval)
+    }
+}
+
+collection_target! {
+    /// Host collection target. Targets can be configured with a plain host or
+    /// with a table which has target specific options, e.g.
+    /// `{ host = "example.com", interval = 60 }`.
+    HostTarget(HostTargetDef) { host: Host, options: () }
+}
+
+collection_target! {
+    /// URL collection target. Targets can be configured with a plain URL or with
+    /// a table which has target specific options, e.g.
+    /// `{ url = "https://example.com/", interval = 60, method = "GET" }`.
+    UrlTarget(UrlTargetDef) { url: HttpUrl, options: HttpOptions }
+}
+
+impl Validate for UrlTarget {
+    fn validate(&self) -> Result<(), ConfigError> {
+        validate_interval("http.urls.interval", self.interval)?;
+        self.options.validate()
+    }
+}
+
+/// HTTP request and response assertion options of a URL target.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct HttpOptions {
+    pub method: HttpMethod,
+    #[serde(
+        skip_serializing_if = "BTreeMap::is_empty",
+        serialize_with = "serialize_headers"
+    )]
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+    pub auth: Option<HttpAuth>,
+    /// Accepted status codes. Any status code is accepted when empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub expected_status: Vec<u16>,
+    pub body_contains: Option<String>,
+    pub body_regex: Option<String>,
+    /// Maximum total response time in milliseconds.
+    pub max_response_time: Option<u64>,
+    /// Accept TLS certificates which fail verification.
+    pub tls_skip_verify: bool,
+    /// PEM file with the CA certificates which are trusted instead of the
+    /// Mozilla root certificates.
+    pub tls_ca_file: Option<PathBuf>,
+}
+
+impl HttpOptions {
+    /// Returns true if any response assertion is configured.
+    pub fn has_assertions(&self) -> bool {
+        !self.expected_status.is_empty()
+            || self.body_contains.is_some()
+            || self.body_regex.is_some()
+            || self.max_response_time.is_some()
+    }
+}
+
+impl Validate for HttpOptions {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.method == HttpMethod::Head && self.body.is_some() {
+            return Err(ConfigError::Message(
+                "http.urls.body can't be used with HEAD method".to_string(),
+            ));
+        }
+        if self.method == HttpMethod::Head
+            && (self.body_contains.is_some() || self.body_regex.is_some())
+        {
+            return Err(ConfigError::Message(
+                "http.urls body assertions can't be used with HEAD method".to_string(),
+            ));
+        }
+        if let Some(status) = self
+            .expected_status
+            .iter()
+            .find(|s| !(100..=599).contains(*s))
+        {
+            return Err(ConfigError::Message(format!(
+                "http.urls.expected_status {} is not a valid status code",
+                status
+            )));
+        }
+        if let Some(regex) = self.body_regex.as_ref() {
+            Regex::new(regex).map_err(|e| {
+                ConfigError::Message(format!("http.urls.body_regex is invalid: {}", e))
+            })?;
+        }
+        if let Some(ca_file) = self.tls_ca_file.as_ref() {
+            if !ca_file.is_file() {
+                return Err(ConfigError::Message(format!(
+                    "http.urls.tls_ca_file {} doesn't exist",
+                    ca_file.display()
+                )));
+            }
+        }
+        if self.max_response_time == Some(0) {
+            return Err(ConfigError::Message(
+                "http.urls.max_response_time minimum value is 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HttpMethod {
+    Get,
+    Post,
+    #[default]
+    Head,
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Head => "HEAD",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HttpAuth {
+    Basic { username: String, password: Secret },
+    Bearer { token: Secret },
+}
+
+collection_target! {
+    /// Throughput probe target. Targets can be configured with a plain URL or with
+    /// a table which has target specific options, e.g.
+    /// `{ url = "https://example.com/file.bin", size = 50, range = true }`.
+    ThroughputTarget(ThroughputTargetDef) { url: HttpUrl, options: ThroughputOptions }
+}
+
+impl Validate for ThroughputTarget {
+    fn validate(&self) -> Result<(), ConfigError> {
+        validate_interval("throughput.interval", self.interval)?;
+        self.options.validate()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ThroughputOptions {
+    /// Maximum download size or upload size in megabytes.
+    pub size: u64,
+    /// Maximum download time or upload time in seconds. The collector
+    /// timeout applies in addition to it.
+    pub max_duration: u64,
+    /// Request only the first `size` megabytes with a range request.
+    pub range: bool,
+    #[serde(
+        skip_serializing_if = "BTreeMap::is_empty",
+        serialize_with = "serialize_headers"
+    )]
+    pub headers: BTreeMap<String, String>,
+    pub auth: Option<HttpAuth>,
+}
+
+impl Validate for ThroughputOptions {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.size < 1 || self.size > 1000 {
+            return Err(ConfigError::Message(
+                "throughput.size minimum value is 1 and maximum value is 1000".to_string(),
+            ));
+        }
+        if self.max_duration < 1 || self.max_duration > 300 {
+            return Err(ConfigError::Message(
+                "throughput.max_duration minimum value is 1 and maximum value is 300".to_string(),
+            ));
+        }
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Default)]
+impl Default for ThroughputOptions {
+    fn default() -> Self {
+        ThroughputOptions {
+            size: 10,
+            max_duration: 10,
+            range: false,
+            headers: BTreeMap::new(),
+            auth: None,
+        }
+    }
+}
+
+/// IP address family which is used to reach the targets. `any` uses the first
+/// resolved address and `both` measures IPv4 and IPv6 separately.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+    Both,
+}
+
+impl AddressFamily {
+    pub fn includes(&self, addr: &IpAddr) -> bool {
+        match self {
+            AddressFamily::Any | AddressFamily::Both => true,
+            AddressFamily::Ipv4 => addr.is_ipv4(),
+            AddressFamily::Ipv6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Validates collection interval in seconds if it's set.
+fn validate_interval(name: &str, interval: Option<u64>) -> Result<(), ConfigError> {
+    match interval {
+        Some(interval) if !(1..=86400).contains(&interval) => Err(ConfigError::Message(format!(
+            "{} minimum value is 1 and maximum value is 86400",
+            name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct ExportersConfig {
+    /// Single exporter selection which is used when `instances` is empty.
     pub exporter: ExporterSelection,
     pub influxdb: InfluxDbConfig,
+    pub prometheus: PrometheusConfig,
+    pub instances: Vec<ExporterInstance>,
+    pub spool: SpoolConfig,
+}
+
+impl ExportersConfig {
+    /// Returns configurations for all the exporter instances which messages
+    /// are exported to. The single selected exporter is named after its
+    /// type.
+    pub fn exporter_configs(&self) -> Vec<ExporterInstance> {
+        if !self.instances.is_empty() {
+            return self.instances.clone();
+        }
+
+        let (name, exporter) = match self.exporter {
+            ExporterSelection::InfluxDb => (
+                "influxdb",
+                ExporterConfig::InfluxDb(Box::new(self.influxdb.clone())),
+            ),
+            ExporterSelection::Prometheus => (
+                "prometheus",
+                ExporterConfig::Prometheus(self.prometheus.clone()),
+            ),
+            ExporterSelection::Stdout => ("stdout", ExporterConfig::Stdout),
+            ExporterSelection::Logger => ("logger", ExporterConfig::Logger),
+        };
+        vec![ExporterInstance {
+            name: Some(name.to_string()),
+            exporter,
+        }]
+    }
 }
 
 impl Validate for ExportersConfig {
     fn validate(&self) -> Result<(), ConfigError> {
-        match self.exporter {
-            ExporterSelection::InfluxDb => self.influxdb.validate(),
-            ExporterSelection::Stdout => Ok(()),
-            ExporterSelection::Logger => Ok(()),
+        let instances = self.exporter_configs();
+        for (index, instance) in instances.iter().enumerate() {
+            instance.exporter.validate()?;
+            // Spool directories are named after the instances so the names
+            // must stay the same when instances are added or reordered.
+            if !self.spool.enabled {
+                continue;
+            }
+            let Some(name) = instance.name.as_deref() else {
+                return Err(ConfigError::Message(
+                    "exporters.instances.name is required when exporters.spool is enabled"
+                        .to_string(),
+                ));
+            };
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid_name {
+                return Err(ConfigError::Message(format!(
+                    "exporters.instances.name {} can only contain letters, digits, '_' and '-'",
+                    name
+                )));
+            }
+            if instances[..index]
+                .iter()
+                .any(|other| other.name.as_deref() == Some(name))
+            {
+                return Err(ConfigError::Message(format!(
+                    "exporters.instances.name {} is not unique",
+                    name
+                )));
+            }
         }
+        self.spool.validate()?;
+        Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Default)]
+/// On-disk queue between collectors and exporters which buffers messages
+/// while exporting fails.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpoolConfig {
+    pub enabled: bool,
+    pub directory: String,
+    /// Maximum size of the spool of a single exporter in megabytes.
+    pub max_size: u64,
+    /// Maximum age of spooled messages in seconds.
+    pub max_age: u64,
+}
+
+impl Validate for SpoolConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.enabled {
+            if self.directory.is_empty() {
+                return Err(ConfigError::Message(
+                    "exporters.spool.directory can't be empty".to_string(),
+                ));
+            }
+            if self.max_size < 1 {
+                return Err(ConfigError::Message(
+                    "exporters.spool.max_size minimum value is 1".to_string(),
+                ));
+            }
+            if self.max_age < 1 {
+                return Err(ConfigError::Message(
+                    "exporters.spool.max_age minimum value is 1".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        SpoolConfig {
+            enabled: false,
+            directory: "/var/lib/uption/spool".to_string(),
+            max_size: 100,
+            max_age: 604800, // 7 days
+        }
+    }
+}
+
+/// Exporter instance of `[[exporters.instances]]`. The name identifies the
+/// spool of the instance and it's required when the spool is enabled.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExporterInstance {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub exporter: ExporterConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ExporterConfig {
+    InfluxDb(Box<InfluxDbConfig>),
+    Prometheus(PrometheusConfig),
+    Stdout,
+    Logger,
+}
+
+impl Validate for ExporterConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        match self {
+            ExporterConfig::InfluxDb(influxdb) => influxdb.validate(),
+            ExporterConfig::Prometheus(prometheus) => prometheus.validate(),
+            ExporterConfig::Stdout => Ok(()),
+            ExporterConfig::Logger => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ExporterSelection {
     InfluxDb,
+    Prometheus,
     #[default]
     Stdout,
     Logger,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct InfluxDbConfig {
     pub url: Option<HttpUrl>,
     pub bucket: String,
     pub organization: String,
-    pub token: String,
+    pub token: Secret,
     pub database: String,
     pub username: String,
-    pub password: String,
+    pub password: Secret,
     pub version: InfluxDbVersion,
     pub timeout: Timeout,
+    pub batch_size: usize,
+    /// Maximum time to wait for a batch to fill in milliseconds.
+    pub batch_max_latency: u64,
 }
 
 impl Validate for InfluxDbConfig {
@@ -273,11 +1251,69 @@ impl Validate for InfluxDbConfig {
             }
         }
 
+        if self.batch_size < 1 || self.batch_size > 10000 {
+            return Err(ConfigError::Message(
+                "influxdb.batch_size minimum value is 1 and maximum value is 10000".to_string(),
+            ));
+        }
+        if self.batch_max_latency > 60000 {
+            return Err(ConfigError::Message(
+                "influxdb.batch_max_latency maximum value is 60000".to_string(),
+            ));
+        }
+
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Default)]
+impl Default for InfluxDbConfig {
+    fn default() -> Self {
+        InfluxDbConfig {
+            url: None,
+            bucket: String::new(),
+            organization: String::new(),
+            token: Secret::default(),
+            database: String::new(),
+            username: String::new(),
+            password: Secret::default(),
+            version: InfluxDbVersion::default(),
+            timeout: Timeout::default(),
+            batch_size: 1000,
+            batch_max_latency: 5000, // 5 seconds
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PrometheusConfig {
+    pub listen_address: SocketAddr,
+    /// Time in seconds after which series that are no longer collected are
+    /// removed.
+    pub expiry: u64,
+}
+
+impl Validate for PrometheusConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.expiry < 1 {
+            return Err(ConfigError::Message(
+                "prometheus.expiry minimum value is 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PrometheusConfig {
+    fn default() -> Self {
+        PrometheusConfig {
+            listen_address: SocketAddr::from(([0, 0, 0, 0], 9367)),
+            expiry: 900, // 15 minutes
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Default, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum InfluxDbVersion {
     V1,
@@ -286,10 +1322,15 @@ pub enum InfluxDbVersion {
 }
 
 impl UptionConfig {
-    pub fn new() -> Result<Self, ConfigError> {
+    /// Loads configuration from the given file or from the default location
+    /// when the path is not set. Environment variables override values from
+    /// the file.
+    pub fn new(path: Option<&Path>) -> Result<Self, ConfigError> {
         let mut s = Config::builder();
 
-        if Path::new("/etc/uption").exists() {
+        if let Some(path) = path {
+            s = s.add_source(File::from(PathBuf::from(path)));
+        } else if Path::new("/etc/uption").exists() {
             s = s.add_source(File::with_name("/etc/uption/uption"));
         } else {
             s = s.add_source(File::with_name("uption"));
@@ -310,9 +1351,76 @@ impl UptionConfig {
 
         Ok(config)
     }
+
+    /// Formats configuration as TOML. Secrets are redacted.
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        toml::to_string_pretty(self).map_err(|e| ConfigError::Foreign(Box::new(e)))
+    }
+}
+
+/// Configuration value which must not be shown when the configuration is
+/// printed.
+#[derive(Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl AsRef<str> for Secret {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Secret(secret.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.is_empty() {
+            serializer.serialize_str("")
+        } else {
+            serializer.serialize_str("<redacted>")
+        }
+    }
+}
+
+/// Serializes HTTP headers with the values of credential headers such as
+/// `Authorization` and `X-Api-Key` redacted.
+fn serialize_headers<S>(
+    headers: &BTreeMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    const SENSITIVE: [&str; 6] = ["auth", "cookie", "key", "token", "secret", "password"];
+    serializer.collect_map(headers.iter().map(|(name, value)| {
+        let name_lower = name.to_ascii_lowercase();
+        if SENSITIVE.iter().any(|part| name_lower.contains(part)) {
+            (name, "<redacted>")
+        } else {
+            (name, value.as_str())
+        }
+    }))
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct Timeout(pub u64);
 
 impl Default for Timeout {
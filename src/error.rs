@@ -2,8 +2,14 @@
 use std::any;
 use std::error;
 use std::fmt;
+use std::io;
+use std::net::AddrParseError;
+use std::num::{ParseFloatError, ParseIntError};
 use std::result;
 
+use hickory_client::error::{ClientError, ClientErrorKind};
+use hickory_client::proto::error::{ProtoError, ProtoErrorKind};
+
 pub type Result<T> = result::Result<T, Error>;
 
 #[derive(Debug)]
@@ -12,6 +18,7 @@ pub struct Error {
     source: Option<String>,
     cause: Option<Box<dyn error::Error>>,
     context: Option<String>,
+    kind: ErrorKind,
 }
 
 impl Error {
@@ -21,6 +28,7 @@ impl Error {
             source: None,
             cause: None,
             context: None,
+            kind: ErrorKind::Other,
         }
     }
 
@@ -34,12 +42,16 @@ impl Error {
         self
     }
 
-    #[allow(dead_code)]
     pub fn cause(&self) -> &Option<Box<dyn error::Error>> {
         &self.cause
     }
 
+    /// Sets the cause of the error. Error kind is derived from the cause if
+    /// it has not been set explicitly.
     pub fn set_cause(mut self, cause: impl error::Error + 'static) -> Self {
+        if self.kind == ErrorKind::Other {
+            self.kind = ErrorKind::from_error(&cause);
+        }
         self.cause = Some(Box::new(cause));
         self
     }
@@ -53,6 +65,15 @@ impl Error {
         self.context = Some(context.to_string());
         self
     }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn set_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
 }
 
 impl fmt::Display for Error {
@@ -77,7 +98,97 @@ impl fmt::Display for Error {
 
 impl<E: error::Error + 'static> From<E> for Error {
     fn from(err: E) -> Self {
-        Error::new(&err.to_string()).set_context(any::type_name::<E>())
+        Error::new(&err.to_string())
+            .set_context(any::type_name::<E>())
+            .set_kind(ErrorKind::from_error(&err))
+    }
+}
+
+/// Error classification which is exported with failed collection results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Timeout,
+    DnsFailure,
+    ConnectionRefused,
+    Unreachable,
+    Tls,
+    HttpStatus,
+    Parse,
+    Other,
+}
+
+impl ErrorKind {
+    /// Classifies an error by walking through its source chain.
+    fn from_error(err: &(dyn error::Error + 'static)) -> Self {
+        let mut next = Some(err);
+        while let Some(err) = next {
+            let kind = if let Some(err) = err.downcast_ref::<io::Error>() {
+                match err.kind() {
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+                    io::ErrorKind::ConnectionRefused => ErrorKind::ConnectionRefused,
+                    io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+                        ErrorKind::Unreachable
+                    }
+                    // TLS errors are wrapped in IO errors by rustls streams
+                    _ if err.get_ref().is_some_and(|err| err.is::<rustls::Error>()) => {
+                        ErrorKind::Tls
+                    }
+                    _ => ErrorKind::Other,
+                }
+            } else if err.is::<rustls::Error>() {
+                ErrorKind::Tls
+            } else if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+                if err.is_timeout() {
+                    ErrorKind::Timeout
+                } else if err.is_status() {
+                    ErrorKind::HttpStatus
+                } else if err.is_decode() {
+                    ErrorKind::Parse
+                } else {
+                    ErrorKind::Other
+                }
+            } else if let Some(err) = err.downcast_ref::<ClientError>() {
+                match err.kind() {
+                    ClientErrorKind::Timeout => ErrorKind::Timeout,
+                    _ => ErrorKind::DnsFailure,
+                }
+            } else if let Some(err) = err.downcast_ref::<ProtoError>() {
+                match err.kind() {
+                    ProtoErrorKind::Timeout => ErrorKind::Timeout,
+                    _ => ErrorKind::DnsFailure,
+                }
+            } else if err.is::<AddrParseError>()
+                || err.is::<ParseIntError>()
+                || err.is::<ParseFloatError>()
+                || err.is::<url::ParseError>()
+            {
+                ErrorKind::Parse
+            } else {
+                ErrorKind::Other
+            };
+
+            if kind != ErrorKind::Other {
+                return kind;
+            }
+            next = err.source();
+        }
+        ErrorKind::Other
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::DnsFailure => "dns_failure",
+            ErrorKind::ConnectionRefused => "connection_refused",
+            ErrorKind::Unreachable => "unreachable",
+            ErrorKind::Tls => "tls_error",
+            ErrorKind::HttpStatus => "http_status",
+            ErrorKind::Parse => "parse_error",
+            ErrorKind::Other => "other",
+        };
+        write!(f, "{}", kind)
     }
 }
 
@@ -90,6 +201,9 @@ pub trait ResultError {
 
     #[allow(dead_code)]
     fn set_context(self, context: &str) -> Self;
+
+    #[allow(dead_code)]
+    fn set_kind(self, kind: ErrorKind) -> Self;
 }
 
 impl<T> ResultError for Result<T> {
@@ -104,4 +218,44 @@ impl<T> ResultError for Result<T> {
     fn set_context(self, context: &str) -> Self {
         self.map_err(|e| e.set_context(context))
     }
+
+    fn set_kind(self, kind: ErrorKind) -> Self {
+        self.map_err(|e| e.set_kind(kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_from_io_error() {
+        let err: Error = io::Error::from(io::ErrorKind::TimedOut).into();
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+        let err: Error = io::Error::from(io::ErrorKind::NetworkUnreachable).into();
+        assert_eq!(err.kind(), ErrorKind::Unreachable);
+    }
+
+    #[test]
+    fn kind_from_parse_error() {
+        let err: Error = "foo".parse::<u8>().unwrap_err().into();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert_eq!(err.kind().to_string(), "parse_error");
+    }
+
+    #[test]
+    fn kind_from_cause() {
+        let cause = io::Error::from(io::ErrorKind::ConnectionRefused);
+        let err = Error::new("error").set_cause(cause);
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn explicit_kind_is_not_overridden_by_cause() {
+        let cause = io::Error::from(io::ErrorKind::TimedOut);
+        let err = Error::new("error")
+            .set_kind(ErrorKind::DnsFailure)
+            .set_cause(cause);
+        assert_eq!(err.kind(), ErrorKind::DnsFailure);
+    }
 }
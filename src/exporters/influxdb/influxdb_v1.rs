@@ -1,14 +1,15 @@
 //! InfluxDB API v1 exporter implementation.
 use std::fmt::Write as _;
+use std::slice;
 use std::str;
 use std::time::Duration;
 
-use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::blocking::{Client, RequestBuilder, Response};
 use reqwest::StatusCode;
 use serde::Deserialize;
 
-use super::InfluxDb;
-use crate::config::{Configure, Timeout, UptionConfig};
+use super::{build_client, InfluxDb, LineProtocol, WriteStatus};
+use crate::config::{InfluxDbConfig, Timeout};
 use crate::error::{Error, Result, ResultError};
 use crate::exporters::Exporter;
 use crate::message::Message;
@@ -24,7 +25,7 @@ pub struct InfluxDbv1 {
     url: HttpUrl,
     username: String,
     password: String,
-    timeout: Duration,
+    client: Client,
 }
 
 impl InfluxDbv1 {
@@ -44,19 +45,23 @@ impl InfluxDbv1 {
             url,
             username: username.to_string(),
             password: password.to_string(),
-            timeout: Duration::from_secs(timeout.into()),
+            client: build_client(Duration::from_secs(timeout.into())),
         }
     }
 }
 
 impl InfluxDb for InfluxDbv1 {
+    // InfluxDB 1.x does not support unsigned integers
+    const LINE_PROTOCOL: LineProtocol = LineProtocol::new(false);
+
     fn set_authentication(&self, req: RequestBuilder) -> RequestBuilder {
         req.basic_auth(&self.username, Some(&self.password))
     }
 
-    fn handle_response_errors(resp: Response) -> Result<()> {
+    fn handle_response_errors(resp: Response) -> Result<WriteStatus> {
         match resp.status() {
-            StatusCode::NO_CONTENT => Ok(()),
+            StatusCode::NO_CONTENT => Ok(WriteStatus::Written),
+            StatusCode::PAYLOAD_TOO_LARGE => Ok(WriteStatus::TooLarge),
             StatusCode::BAD_REQUEST => {
                 let mut err = format!(
                     "InfluxDB server returned HTTP status '{}' Bad Request",
@@ -66,10 +71,7 @@ impl InfluxDb for InfluxDbv1 {
                 if let Ok(body) = resp.json::<ErrorResponse>() {
                     write!(err, ": {}", &body.error).unwrap();
                 };
-                log::error!("{}", err);
-
-                // Do not retry on bad request
-                Ok(())
+                Ok(WriteStatus::Rejected(err))
             }
             _ => {
                 let err = Error::new(&format!(
@@ -88,8 +90,8 @@ impl InfluxDb for InfluxDbv1 {
         }
     }
 
-    fn timeout(&self) -> Duration {
-        self.timeout
+    fn client(&self) -> &Client {
+        &self.client
     }
 
     fn url(&self) -> &HttpUrl {
@@ -99,19 +101,23 @@ impl InfluxDb for InfluxDbv1 {
 
 impl Exporter for InfluxDbv1 {
     fn export(&self, message: &Message) -> Result<()> {
-        self.send_to_influxdb(message)
+        self.export_batch(slice::from_ref(message))
+    }
+
+    fn export_batch(&self, messages: &[Message]) -> Result<()> {
+        self.send_to_influxdb(messages)
             .set_source("influxdb_v1_exporter")
     }
 }
 
-impl Configure for InfluxDbv1 {
-    fn from_config(config: &UptionConfig) -> Self {
+impl From<&InfluxDbConfig> for InfluxDbv1 {
+    fn from(config: &InfluxDbConfig) -> Self {
         InfluxDbv1::new(
-            config.exporters.influxdb.url.as_ref().unwrap(),
-            config.exporters.influxdb.database.as_ref(),
-            config.exporters.influxdb.username.as_ref(),
-            config.exporters.influxdb.password.as_ref(),
-            config.exporters.influxdb.timeout,
+            config.url.as_ref().unwrap(),
+            config.database.as_ref(),
+            config.username.as_ref(),
+            config.password.as_ref(),
+            config.timeout,
         )
     }
 }
@@ -144,7 +150,7 @@ mod tests {
             .with_header("content-type", "text/plain")
             .with_header("authorization", "Basic token")
             .match_body(Regex(
-                r#"^measurement,tag1=1,tag2=2 field1="foo",field2=2 \d{13}$"#.to_string(),
+                r#"^measurement,tag1=1,tag2=2 field1="foo",field2=2i \d{13}$"#.to_string(),
             ))
             .create();
 
@@ -191,4 +197,94 @@ mod tests {
         m.assert();
         assert!(result.is_ok());
     }
+
+    #[rstest]
+    fn split_too_large_batch(message: Message) {
+        let mut other = message.clone();
+        other.insert_metric("field1", "bar");
+        let mut server = mockito::Server::new();
+        let batch = server
+            .mock("POST", "/write?db=uption&precision=ms")
+            .with_status(413)
+            .match_body(Regex(r#"foo.*\n.*bar"#.to_string()))
+            .expect(1)
+            .create();
+        let first = server
+            .mock("POST", "/write?db=uption&precision=ms")
+            .with_status(204)
+            .match_body(Regex(r#"^[^\n]*foo[^\n]*$"#.to_string()))
+            .expect(1)
+            .create();
+        let second = server
+            .mock("POST", "/write?db=uption&precision=ms")
+            .with_status(204)
+            .match_body(Regex(r#"^[^\n]*bar[^\n]*$"#.to_string()))
+            .expect(1)
+            .create();
+
+        let url: HttpUrl = server.url().parse().unwrap();
+        let exporter = InfluxDbv1::new(&url, "uption", "user", "pass", Timeout(1));
+        let result = exporter.export_batch(&[message, other]);
+
+        batch.assert();
+        first.assert();
+        second.assert();
+        assert!(result.is_ok());
+    }
+
+    #[rstest]
+    fn drop_only_rejected_lines(message: Message) {
+        let mut rejected = message.clone();
+        rejected.insert_metric("field1", "bad");
+        let mut server = mockito::Server::new();
+        let batch = server
+            .mock("POST", "/write?db=uption&precision=ms")
+            .with_status(400)
+            .with_body("{\"error\": \"field type conflict\"}")
+            .match_body(Regex(r#"\n"#.to_string()))
+            .expect(2)
+            .create();
+        let valid = server
+            .mock("POST", "/write?db=uption&precision=ms")
+            .with_status(204)
+            .match_body(Regex(r#"^[^\n]*foo[^\n]*$"#.to_string()))
+            .expect(2)
+            .create();
+        let invalid = server
+            .mock("POST", "/write?db=uption&precision=ms")
+            .with_status(400)
+            .with_body("{\"error\": \"field type conflict\"}")
+            .match_body(Regex(r#"^[^\n]*bad[^\n]*$"#.to_string()))
+            .expect(1)
+            .create();
+
+        let url: HttpUrl = server.url().parse().unwrap();
+        let exporter = InfluxDbv1::new(&url, "uption", "user", "pass", Timeout(1));
+        let result = exporter.export_batch(&[message.clone(), rejected, message]);
+
+        batch.assert();
+        valid.assert();
+        invalid.assert();
+        assert!(result.is_ok());
+    }
+
+    #[rstest]
+    fn export_batch_in_single_request(message: Message) {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("POST", "/write?db=uption&precision=ms")
+            .with_status(204)
+            .match_body(Regex(
+                r#"^measurement,tag1=1,tag2=2 field1="foo",field2=2i \d{13}\nmeasurement,tag1=1,tag2=2 field1="foo",field2=2i \d{13}$"#.to_string(),
+            ))
+            .expect(1)
+            .create();
+
+        let url: HttpUrl = server.url().parse().unwrap();
+        let exporter = InfluxDbv1::new(&url, "uption", "user", "pass", Timeout(1));
+        let result = exporter.export_batch(&[message.clone(), message]);
+
+        m.assert();
+        assert!(result.is_ok());
+    }
 }
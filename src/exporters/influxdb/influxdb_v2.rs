@@ -1,15 +1,16 @@
 //! InfluxDB API v2 exporter implementation.
 use std::fmt::Write as _;
+use std::slice;
 use std::str;
 use std::time::Duration;
 
-use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::blocking::{Client, RequestBuilder, Response};
 use reqwest::header::{self, HeaderMap, HeaderValue};
 use reqwest::StatusCode;
 use serde::Deserialize;
 
-use super::InfluxDb;
-use crate::config::{Configure, Timeout, UptionConfig};
+use super::{build_client, InfluxDb, LineProtocol, WriteStatus};
+use crate::config::{InfluxDbConfig, Timeout};
 use crate::error::{Error, Result, ResultError};
 use crate::exporters::Exporter;
 use crate::message::Message;
@@ -24,7 +25,7 @@ struct ErrorResponse {
 pub struct InfluxDbv2 {
     url: HttpUrl,
     token: String,
-    timeout: Duration,
+    client: Client,
 }
 
 impl InfluxDbv2 {
@@ -44,7 +45,7 @@ impl InfluxDbv2 {
         InfluxDbv2 {
             url,
             token: String::from(token),
-            timeout: Duration::from_secs(timeout.into()),
+            client: build_client(Duration::from_secs(timeout.into())),
         }
     }
 
@@ -54,6 +55,8 @@ impl InfluxDbv2 {
 }
 
 impl InfluxDb for InfluxDbv2 {
+    const LINE_PROTOCOL: LineProtocol = LineProtocol::new(true);
+
     fn set_authentication(&self, req: RequestBuilder) -> RequestBuilder {
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -64,22 +67,21 @@ impl InfluxDb for InfluxDbv2 {
         req.headers(headers)
     }
 
-    fn handle_response_errors(resp: Response) -> Result<()> {
+    fn handle_response_errors(resp: Response) -> Result<WriteStatus> {
         match resp.status() {
-            StatusCode::NO_CONTENT => Ok(()),
-            StatusCode::BAD_REQUEST => {
+            StatusCode::NO_CONTENT => Ok(WriteStatus::Written),
+            StatusCode::PAYLOAD_TOO_LARGE => Ok(WriteStatus::TooLarge),
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
                 let mut err = format!(
-                    "InfluxDB server returned HTTP status '{}' Bad Request",
-                    resp.status().as_u16()
+                    "InfluxDB server returned HTTP status '{}' {}",
+                    resp.status().as_u16(),
+                    resp.status().canonical_reason().unwrap_or_default()
                 );
                 // Set message field to error context if returned
                 if let Ok(body) = resp.json::<ErrorResponse>() {
                     write!(err, ": {}", &body.message).unwrap();
                 };
-                log::error!("{}", err);
-
-                // Do not retry on bad request
-                Ok(())
+                Ok(WriteStatus::Rejected(err))
             }
             _ => {
                 let err = Error::new(&format!(
@@ -98,8 +100,8 @@ impl InfluxDb for InfluxDbv2 {
         }
     }
 
-    fn timeout(&self) -> Duration {
-        self.timeout
+    fn client(&self) -> &Client {
+        &self.client
     }
 
     fn url(&self) -> &HttpUrl {
@@ -109,19 +111,23 @@ impl InfluxDb for InfluxDbv2 {
 
 impl Exporter for InfluxDbv2 {
     fn export(&self, message: &Message) -> Result<()> {
-        self.send_to_influxdb(message)
+        self.export_batch(slice::from_ref(message))
+    }
+
+    fn export_batch(&self, messages: &[Message]) -> Result<()> {
+        self.send_to_influxdb(messages)
             .set_source("influxdb_v2_exporter")
     }
 }
 
-impl Configure for InfluxDbv2 {
-    fn from_config(config: &UptionConfig) -> Self {
+impl From<&InfluxDbConfig> for InfluxDbv2 {
+    fn from(config: &InfluxDbConfig) -> Self {
         InfluxDbv2::new(
-            config.exporters.influxdb.url.as_ref().unwrap(),
-            config.exporters.influxdb.bucket.as_ref(),
-            config.exporters.influxdb.organization.as_ref(),
-            config.exporters.influxdb.token.as_ref(),
-            config.exporters.influxdb.timeout,
+            config.url.as_ref().unwrap(),
+            config.bucket.as_ref(),
+            config.organization.as_ref(),
+            config.token.as_ref(),
+            config.timeout,
         )
     }
 }
@@ -154,7 +160,7 @@ mod tests {
             .with_header("content-type", "text/plain")
             .with_header("authorization", "Token token")
             .match_body(Regex(
-                r#"^measurement,tag1=1,tag2=2 field1="foo",field2=2 \d{13}$"#.to_string(),
+                r#"^measurement,tag1=1,tag2=2 field1="foo",field2=2i \d{13}$"#.to_string(),
             ))
             .create();
 
@@ -201,4 +207,94 @@ mod tests {
         m.assert();
         assert!(result.is_ok());
     }
+
+    #[rstest]
+    fn split_too_large_batch(message: Message) {
+        let mut other = message.clone();
+        other.insert_metric("field1", "bar");
+        let mut server = mockito::Server::new();
+        let batch = server
+            .mock("POST", "/api/v2/write?bucket=bucket&org=org&precision=ms")
+            .with_status(413)
+            .match_body(Regex(r#"foo.*\n.*bar"#.to_string()))
+            .expect(1)
+            .create();
+        let first = server
+            .mock("POST", "/api/v2/write?bucket=bucket&org=org&precision=ms")
+            .with_status(204)
+            .match_body(Regex(r#"^[^\n]*foo[^\n]*$"#.to_string()))
+            .expect(1)
+            .create();
+        let second = server
+            .mock("POST", "/api/v2/write?bucket=bucket&org=org&precision=ms")
+            .with_status(204)
+            .match_body(Regex(r#"^[^\n]*bar[^\n]*$"#.to_string()))
+            .expect(1)
+            .create();
+
+        let url: HttpUrl = server.url().parse().unwrap();
+        let exporter = InfluxDbv2::new(&url, "bucket", "org", "token", Timeout(1));
+        let result = exporter.export_batch(&[message, other]);
+
+        batch.assert();
+        first.assert();
+        second.assert();
+        assert!(result.is_ok());
+    }
+
+    #[rstest]
+    fn drop_only_rejected_lines(message: Message) {
+        let mut rejected = message.clone();
+        rejected.insert_metric("field1", "bad");
+        let mut server = mockito::Server::new();
+        let batch = server
+            .mock("POST", "/api/v2/write?bucket=bucket&org=org&precision=ms")
+            .with_status(400)
+            .with_body("{\"message\": \"field type conflict\"}")
+            .match_body(Regex(r#"\n"#.to_string()))
+            .expect(2)
+            .create();
+        let valid = server
+            .mock("POST", "/api/v2/write?bucket=bucket&org=org&precision=ms")
+            .with_status(204)
+            .match_body(Regex(r#"^[^\n]*foo[^\n]*$"#.to_string()))
+            .expect(2)
+            .create();
+        let invalid = server
+            .mock("POST", "/api/v2/write?bucket=bucket&org=org&precision=ms")
+            .with_status(400)
+            .with_body("{\"message\": \"field type conflict\"}")
+            .match_body(Regex(r#"^[^\n]*bad[^\n]*$"#.to_string()))
+            .expect(1)
+            .create();
+
+        let url: HttpUrl = server.url().parse().unwrap();
+        let exporter = InfluxDbv2::new(&url, "bucket", "org", "token", Timeout(1));
+        let result = exporter.export_batch(&[message.clone(), rejected, message]);
+
+        batch.assert();
+        valid.assert();
+        invalid.assert();
+        assert!(result.is_ok());
+    }
+
+    #[rstest]
+    fn export_batch_in_single_request(message: Message) {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("POST", "/api/v2/write?bucket=bucket&org=org&precision=ms")
+            .with_status(204)
+            .match_body(Regex(
+                r#"^measurement,tag1=1,tag2=2 field1="foo",field2=2i \d{13}\nmeasurement,tag1=1,tag2=2 field1="foo",field2=2i \d{13}$"#.to_string(),
+            ))
+            .expect(1)
+            .create();
+
+        let url: HttpUrl = server.url().parse().unwrap();
+        let exporter = InfluxDbv2::new(&url, "bucket", "org", "token", Timeout(1));
+        let result = exporter.export_batch(&[message.clone(), message]);
+
+        m.assert();
+        assert!(result.is_ok());
+    }
 }
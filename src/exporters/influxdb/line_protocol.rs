@@ -0,0 +1,318 @@
+//! InfluxDB line protocol encoder.
+//!
+//! See https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/
+use std::fmt::Write;
+
+use log::warn;
+
+use crate::message::{Message, PayloadValue};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LineProtocol {
+    /// Unsigned integers are written with the `u` suffix when supported and
+    /// as signed integers otherwise.
+    unsigned_integers: bool,
+}
+
+impl LineProtocol {
+    pub const fn new(unsigned_integers: bool) -> Self {
+        Self { unsigned_integers }
+    }
+
+    /// Encodes a message to a line. Returns `None` if the message has no
+    /// fields which could be encoded.
+    pub fn encode(&self, msg: &Message) -> Option<String> {
+        let mut fields = Vec::new();
+        for (key, value) in msg.metrics().iter() {
+            match self.encode_field_value(value) {
+                Some(value) => fields.push(format!("{}={}", escape_key(key), value)),
+                None => warn!(
+                    "Skipping field '{}' with value '{}' which can't be written to InfluxDB",
+                    key, value
+                ),
+            }
+        }
+        if fields.is_empty() {
+            return None;
+        }
+
+        let mut line = escape_measurement(msg.source());
+        for (key, value) in msg.tags().iter() {
+            // Tags with empty values are not allowed
+            if value.is_empty() {
+                continue;
+            }
+            write!(line, ",{}={}", escape_key(key), escape_key(value)).unwrap();
+        }
+        write!(
+            line,
+            " {} {}",
+            fields.join(","),
+            msg.timestamp().timestamp_millis()
+        )
+        .unwrap();
+
+        Some(line)
+    }
+
+    fn encode_field_value(&self, value: &PayloadValue) -> Option<String> {
+        let value = match value {
+            PayloadValue::String(val) => format!("\"{}\"", escape_string(val)),
+            PayloadValue::Int8(val) => format!("{}i", val),
+            PayloadValue::Int16(val) => format!("{}i", val),
+            PayloadValue::Int32(val) => format!("{}i", val),
+            PayloadValue::Int64(val) => format!("{}i", val),
+            PayloadValue::Int128(val) => format!("{}i", i64::try_from(*val).ok()?),
+            PayloadValue::Uint8(val) => self.encode_unsigned(u64::from(*val))?,
+            PayloadValue::Uint16(val) => self.encode_unsigned(u64::from(*val))?,
+            PayloadValue::Uint32(val) => self.encode_unsigned(u64::from(*val))?,
+            PayloadValue::Uint64(val) => self.encode_unsigned(*val)?,
+            PayloadValue::Uint128(val) => self.encode_unsigned(u64::try_from(*val).ok()?)?,
+            PayloadValue::Float32(val) if val.is_finite() => val.to_string(),
+            PayloadValue::Float64(val) if val.is_finite() => val.to_string(),
+            PayloadValue::Float32(_) | PayloadValue::Float64(_) => return None,
+        };
+        Some(value)
+    }
+
+    fn encode_unsigned(&self, value: u64) -> Option<String> {
+        if self.unsigned_integers {
+            Some(format!("{}u", value))
+        } else {
+            Some(format!("{}i", i64::try_from(value).ok()?))
+        }
+    }
+}
+
+/// Escapes commas, spaces and backslashes in measurement names. Newlines are
+/// not allowed and they are replaced with spaces.
+fn escape_measurement(value: &str) -> String {
+    escape(&value.replace('\n', " "), &[',', ' ', '\\'])
+}
+
+/// Escapes commas, equals signs, spaces and backslashes in tag keys, tag
+/// values and field keys. Unescaped backslash at the end of a tag value would
+/// escape the separator which follows it. Newlines are replaced with spaces.
+fn escape_key(value: &str) -> String {
+    escape(&value.replace('\n', " "), &[',', '=', ' ', '\\'])
+}
+
+/// Escapes double quotes and backslashes in string field values. Newlines are
+/// allowed inside the quotes and they are kept.
+fn escape_string(value: &str) -> String {
+    escape(value, &['"', '\\'])
+}
+
+/// Escapes special characters with a backslash.
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    const V1: LineProtocol = LineProtocol::new(false);
+    const V2: LineProtocol = LineProtocol::new(true);
+
+    /// Splits a line at separators which are not escaped or inside a quoted
+    /// string.
+    fn split(line: &str, separator: char, limit: usize) -> Vec<String> {
+        let mut parts = vec![String::new()];
+        let mut chars = line.chars();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    parts.last_mut().unwrap().push(c);
+                    if let Some(next) = chars.next() {
+                        parts.last_mut().unwrap().push(next);
+                    }
+                }
+                '"' => {
+                    quoted = !quoted;
+                    parts.last_mut().unwrap().push(c);
+                }
+                c if c == separator && !quoted && parts.len() < limit => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+        parts
+    }
+
+    fn unescape(value: &str) -> String {
+        let mut unescaped = String::new();
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(next) => unescaped.push(next),
+                    None => unescaped.push(c),
+                },
+                c => unescaped.push(c),
+            }
+        }
+        unescaped
+    }
+
+    /// Parses a line to measurement, tags and fields with unescaped values.
+    #[allow(clippy::type_complexity)]
+    fn parse(line: &str) -> (String, Vec<(String, String)>, Vec<(String, String)>) {
+        let sections = split(line, ' ', 3);
+        assert_eq!(sections.len(), 3, "invalid line: {}", line);
+
+        let key_values = |section: &str| {
+            split(section, ',', usize::MAX)
+                .iter()
+                .map(|pair| {
+                    let pair = split(pair, '=', 2);
+                    (unescape(&pair[0]), pair[1].clone())
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut series = split(&sections[0], ',', 2);
+        let measurement = unescape(&series.remove(0));
+        let tags = series
+            .first()
+            .map(|tags| key_values(tags))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, unescape(&v)))
+            .collect();
+        let fields = key_values(&sections[1]);
+
+        (measurement, tags, fields)
+    }
+
+    #[test]
+    fn encode_message() {
+        let mut msg = Message::new("measurement");
+        msg.insert_tag("tag1", "1");
+        msg.insert_tag("tag2", "2");
+        msg.insert_metric("field1", "foo");
+        msg.insert_metric("field2", 2.5);
+
+        assert_eq!(
+            V2.encode(&msg).unwrap(),
+            format!(
+                "measurement,tag1=1,tag2=2 field1=\"foo\",field2=2.5 {}",
+                msg.timestamp().timestamp_millis()
+            )
+        );
+    }
+
+    #[rstest]
+    #[case("my measurement", r"my\ measurement")]
+    #[case("my,measurement", r"my\,measurement")]
+    #[case("my=measurement", r"my=measurement")]
+    #[case(r"my\measurement", r"my\\measurement")]
+    #[case("my\nmeasurement", r"my\ measurement")]
+    fn escape_measurement_names(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(escape_measurement(value), expected);
+    }
+
+    #[rstest]
+    #[case("my ssid", r"my\ ssid")]
+    #[case("a,b", r"a\,b")]
+    #[case("a=b", r"a\=b")]
+    #[case("line\nbreak", r"line\ break")]
+    #[case(r"C:\", r"C:\\")]
+    #[case(r"a\,b", r"a\\\,b")]
+    fn escape_keys_and_tag_values(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(escape_key(value), expected);
+    }
+
+    #[rstest]
+    #[case(r#"say "hi""#, r#"say \"hi\""#)]
+    #[case(r"C:\path", r"C:\\path")]
+    #[case("a b,c=d", "a b,c=d")]
+    #[case("line\nbreak", "line\nbreak")]
+    fn escape_string_values(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(escape_string(value), expected);
+    }
+
+    #[rstest]
+    #[case(PayloadValue::Int8(-8), V1, Some("-8i"))]
+    #[case(PayloadValue::Int64(i64::MIN), V1, Some("-9223372036854775808i"))]
+    #[case(PayloadValue::Int128(42), V1, Some("42i"))]
+    #[case(PayloadValue::Int128(i128::MAX), V1, None)]
+    #[case(PayloadValue::Uint16(200), V1, Some("200i"))]
+    #[case(PayloadValue::Uint16(200), V2, Some("200u"))]
+    #[case(PayloadValue::Uint64(u64::MAX), V1, None)]
+    #[case(PayloadValue::Uint64(u64::MAX), V2, Some("18446744073709551615u"))]
+    #[case(PayloadValue::Uint128(7), V2, Some("7u"))]
+    #[case(PayloadValue::Uint128(u128::MAX), V2, None)]
+    #[case(PayloadValue::Float64(1.5), V2, Some("1.5"))]
+    #[case(PayloadValue::Float64(f64::NAN), V2, None)]
+    #[case(PayloadValue::Float32(f32::INFINITY), V2, None)]
+    fn encode_field_values(
+        #[case] value: PayloadValue,
+        #[case] protocol: LineProtocol,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(protocol.encode_field_value(&value).as_deref(), expected);
+    }
+
+    #[test]
+    fn message_without_metrics_is_not_encoded() {
+        let mut msg = Message::new("measurement");
+        msg.insert_tag("tag", "value");
+        msg.insert_metric("nan", f64::NAN);
+
+        assert!(V2.encode(&msg).is_none());
+    }
+
+    #[test]
+    fn empty_tag_values_are_skipped() {
+        let mut msg = Message::new("measurement");
+        msg.insert_tag("empty", "");
+        msg.insert_metric("field", 1u8);
+
+        let (_, tags, _) = parse(&V2.encode(&msg).unwrap());
+        assert!(tags.is_empty());
+    }
+
+    #[test]
+    fn round_trip_special_characters() {
+        let mut msg = Message::new("wireless, station");
+        msg.insert_tag("ssid", "My Wifi,5=GHz");
+        msg.insert_tag("tag key", "line\nbreak");
+        msg.insert_tag("trailing", "backslash\\");
+        msg.insert_metric("field key", "quote \" and \\ backslash");
+        msg.insert_metric("count", 3u32);
+        msg.insert_metric("text", "multi\nline");
+
+        let line = V2.encode(&msg).unwrap();
+        let (measurement, tags, fields) = parse(&line);
+
+        assert_eq!(measurement, "wireless, station");
+        assert_eq!(
+            tags,
+            vec![
+                ("ssid".to_string(), "My Wifi,5=GHz".to_string()),
+                ("tag key".to_string(), "line break".to_string()),
+                ("trailing".to_string(), "backslash\\".to_string()),
+            ]
+        );
+        assert_eq!(
+            fields,
+            vec![
+                ("count".to_string(), "3u".to_string()),
+                (
+                    "field key".to_string(),
+                    r#""quote \" and \\ backslash""#.to_string()
+                ),
+                ("text".to_string(), "\"multi\nline\"".to_string()),
+            ]
+        );
+    }
+}
@@ -1,6 +1,8 @@
-//! InfluxDB exporter.
+//! InfluxDB exporter. Messages are written in batches and the HTTP client is
+//! reused between writes.
 mod influxdb_v1;
 mod influxdb_v2;
+mod line_protocol;
 
 use std::time::Duration;
 
@@ -8,55 +10,83 @@ use reqwest::blocking::{Client, RequestBuilder, Response};
 
 use crate::error::Result;
 use crate::message::Message;
-use crate::message::PayloadValue;
 use crate::url::HttpUrl;
+use line_protocol::LineProtocol;
 
 pub use influxdb_v1::InfluxDbv1;
 pub use influxdb_v2::InfluxDbv2;
 
+/// Outcome of a write request that InfluxDB answered.
+enum WriteStatus {
+    Written,
+    /// Some of the lines were rejected, e.g. because of a field type
+    /// conflict. Contains the error returned by the server.
+    Rejected(String),
+    /// Request body exceeds the maximum size accepted by the server.
+    TooLarge,
+}
+
 trait InfluxDb {
+    const LINE_PROTOCOL: LineProtocol;
+
     fn set_authentication(&self, req: RequestBuilder) -> RequestBuilder;
 
-    fn handle_response_errors(resp: Response) -> Result<()>;
+    fn handle_response_errors(resp: Response) -> Result<WriteStatus>;
 
-    fn timeout(&self) -> Duration;
+    fn client(&self) -> &Client;
 
     fn url(&self) -> &HttpUrl;
 
-    fn send_to_influxdb(&self, message: &Message) -> Result<()> {
-        let payload = Self::message_to_line_protocol(message);
-        let resp = self.send_request(payload)?;
-        Self::handle_response_errors(resp)
-    }
-
-    fn message_to_line_protocol(msg: &Message) -> String {
-        let mut tags = vec![msg.source().to_string()];
-        for (key, value) in msg.tags().iter() {
-            tags.push(format!("{}={}", key, value));
+    fn send_to_influxdb(&self, messages: &[Message]) -> Result<()> {
+        let lines: Vec<String> = messages
+            .iter()
+            .filter_map(|message| Self::LINE_PROTOCOL.encode(message))
+            .collect();
+        if lines.is_empty() {
+            return Ok(());
         }
 
-        let mut fields = Vec::new();
-        for (key, value) in msg.metrics().iter() {
-            if let PayloadValue::String(str_value) = value {
-                fields.push(format!("{}=\"{}\"", key, str_value));
-            } else {
-                fields.push(format!("{}={}", key, value));
+        self.write_lines(&lines)
+    }
+
+    /// Writes lines in one request. A batch which is too large or has
+    /// rejected lines is split in halves which are written separately, so only
+    /// the lines InfluxDB does not accept are dropped. Valid lines may be
+    /// written more than once, which overwrites the same points.
+    fn write_lines(&self, lines: &[String]) -> Result<()> {
+        let resp = self.send_request(lines.join("\n"))?;
+        let status = Self::handle_response_errors(resp)?;
+        match status {
+            WriteStatus::Written => Ok(()),
+            WriteStatus::Rejected(_) | WriteStatus::TooLarge if lines.len() > 1 => {
+                let (first, second) = lines.split_at(lines.len() / 2);
+                self.write_lines(first)?;
+                self.write_lines(second)
+            }
+            WriteStatus::Rejected(err) => {
+                log::error!("{}, dropping line: {}", err, lines[0]);
+                Ok(())
+            }
+            WriteStatus::TooLarge => {
+                log::error!("InfluxDB server rejected too large line: {}", lines[0]);
+                Ok(())
             }
         }
-
-        format!(
-            "{} {} {}",
-            tags.join(","),
-            fields.join(","),
-            msg.timestamp().timestamp_millis()
-        )
     }
 
     fn send_request(&self, payload: String) -> Result<Response> {
-        let client = Client::builder().timeout(self.timeout()).build()?;
-        let req = client.post(self.url().as_str()).body(payload);
+        let req = self.client().post(self.url().as_str()).body(payload);
         let resp = self.set_authentication(req).send()?;
 
         Ok(resp)
     }
 }
+
+/// Builds HTTP client which keeps connections to InfluxDB server open between
+/// writes.
+fn build_client(timeout: Duration) -> Client {
+    Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("Failed to build InfluxDB HTTP client")
+}
@@ -3,28 +3,40 @@
 //! external systems.
 mod influxdb;
 mod logger;
+mod prometheus;
+mod spool;
 mod stdout;
 
+use std::path::Path;
+use std::time::Instant;
 use std::{thread, time::Duration};
 
 extern crate rand;
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, RecvTimeoutError, TryRecvError};
 use log::{debug, error, info, warn};
 use rand::Rng;
 
-use crate::config::{Configure, ExporterSelection, InfluxDbVersion, UptionConfig};
+use crate::config::{ExporterConfig, InfluxDbVersion, SpoolConfig, UptionConfig};
 use crate::error::{Error, Result};
 use crate::message::Message;
 pub use influxdb::{InfluxDbv1, InfluxDbv2};
 pub use logger::Logger;
+pub use prometheus::Prometheus;
+pub use spool::Spool;
 pub use stdout::Stdout;
 
 const ZERO_DURATION: Duration = Duration::from_secs(0);
+const SPOOL_REPORT_INTERVAL: Duration = Duration::from_secs(60);
 
-/// Handles exporting messages by the selected exporter implementation.
+/// Handles exporting messages by the selected exporter implementation. Each
+/// configured exporter instance has its own scheduler with independent retry
+/// state so that a failing exporter does not stall the others.
 pub struct ExporterScheduler {
     exporter: Box<dyn Exporter + Send>,
     retry_buffer: RetryItem,
+    spool: Option<SpoolItem>,
+    batch_size: usize,
+    batch_max_latency: Duration,
 }
 
 impl ExporterScheduler {
@@ -32,46 +44,221 @@ impl ExporterScheduler {
         ExporterScheduler {
             exporter: Box::new(exporter),
             retry_buffer: RetryItem::new(Duration::from_secs(120)),
+            spool: None,
+            batch_size: 1,
+            batch_max_latency: ZERO_DURATION,
         }
     }
 
+    /// Exports messages in batches of at most `size` messages. A batch is
+    /// exported when it is full or when its first message has waited for
+    /// `max_latency`.
+    pub fn set_batching(&mut self, size: usize, max_latency: Duration) {
+        self.batch_size = size;
+        self.batch_max_latency = max_latency;
+    }
+
+    /// Buffers messages to the spool before exporting them.
+    pub fn set_spool(&mut self, spool: Spool, name: &str, hostname: &str) {
+        self.spool = Some(SpoolItem {
+            spool,
+            name: name.to_string(),
+            hostname: hostname.to_string(),
+            last_report: None,
+        });
+    }
+
+    /// Creates a scheduler for every exporter instance in the configuration.
+    pub fn all_from_config(config: &UptionConfig) -> Result<Vec<ExporterScheduler>> {
+        let spool_config = &config.exporters.spool;
+        config
+            .exporters
+            .exporter_configs()
+            .iter()
+            .map(|instance| {
+                let mut scheduler = ExporterScheduler::from_exporter_config(&instance.exporter)?;
+                // Instances are named when the spool is enabled.
+                if let Some(name) = instance.name.as_deref().filter(|_| spool_config.enabled) {
+                    match open_spool(spool_config, name) {
+                        Ok(spool) => scheduler.set_spool(spool, name, &config.general.hostname),
+                        Err(err) => error!("{}", err),
+                    }
+                }
+                Ok(scheduler)
+            })
+            .collect()
+    }
+
+    /// Creates a scheduler for every exporter instance in the configuration
+    /// ignoring the spool configuration.
+    pub fn all_without_spool(config: &UptionConfig) -> Result<Vec<ExporterScheduler>> {
+        config
+            .exporters
+            .exporter_configs()
+            .iter()
+            .map(|instance| ExporterScheduler::from_exporter_config(&instance.exporter))
+            .collect()
+    }
+
+    fn from_exporter_config(config: &ExporterConfig) -> Result<ExporterScheduler> {
+        let scheduler = match config {
+            ExporterConfig::InfluxDb(influxdb) => {
+                let mut scheduler = match influxdb.version {
+                    InfluxDbVersion::V1 => {
+                        ExporterScheduler::new(InfluxDbv1::from(influxdb.as_ref()))
+                    }
+                    InfluxDbVersion::V2 => {
+                        ExporterScheduler::new(InfluxDbv2::from(influxdb.as_ref()))
+                    }
+                };
+                scheduler.set_batching(
+                    influxdb.batch_size,
+                    Duration::from_millis(influxdb.batch_max_latency),
+                );
+                scheduler
+            }
+            ExporterConfig::Prometheus(prometheus) => {
+                ExporterScheduler::new(Prometheus::try_from(prometheus)?)
+            }
+            ExporterConfig::Stdout => ExporterScheduler::new(Stdout::new()),
+            ExporterConfig::Logger => ExporterScheduler::new(Logger::new()),
+        };
+        Ok(scheduler)
+    }
+
     pub fn start(&mut self, receiver: Receiver<Message>) {
         info!("Exporter scheduler started");
 
-        while let Some(message) = self.receive(&receiver) {
-            self.export(message);
+        if let Some(spool) = self.spool.take() {
+            self.start_with_spool(spool, receiver);
+            return;
+        }
+
+        while let Some(batch) = self.receive(&receiver) {
+            self.export(batch);
             self.backoff_sleep();
         }
 
         error!("Collectors disconnected. Stopping exporter.");
     }
 
-    fn receive(&mut self, receiver: &Receiver<Message>) -> Option<Message> {
-        match self.retry_buffer.take() {
-            Some(message) => Some(message),
-            None => match receiver.recv() {
-                Ok(message) => Some(message),
-                Err(_) => None,
-            },
+    /// Exports messages in batches without retrying failed exports.
+    pub fn export_once(&self, messages: &[Message]) -> Result<()> {
+        for batch in messages.chunks(self.batch_size) {
+            self.exporter.export_batch(batch)?;
+            debug!("Exported {} messages", batch.len());
         }
+        Ok(())
     }
 
-    fn export(&mut self, message: Message) {
-        match self.exporter.export(&message) {
+    fn receive(&mut self, receiver: &Receiver<Message>) -> Option<Vec<Message>> {
+        if let Some(batch) = self.retry_buffer.take() {
+            return Some(batch);
+        }
+
+        let mut batch = vec![receiver.recv().ok()?];
+        let deadline = Instant::now() + self.batch_max_latency;
+        while batch.len() < self.batch_size {
+            match receiver.recv_deadline(deadline) {
+                Ok(message) => batch.push(message),
+                Err(_) => break,
+            }
+        }
+        Some(batch)
+    }
+
+    fn export(&mut self, batch: Vec<Message>) {
+        match self.exporter.export_batch(&batch) {
             Ok(_) => {
-                debug!("Exported message from {} collector", message.source());
+                debug!("Exported {} messages", batch.len());
                 self.retry_buffer.decrement_error_count();
             }
-            Err(err) => self.handle_export_error(message, err),
+            Err(err) => self.handle_export_error(batch, err),
         }
     }
 
-    fn handle_export_error(&mut self, message: Message, err: Error) {
+    fn handle_export_error(&mut self, batch: Vec<Message>, err: Error) {
         error!("{}", err);
-        self.retry_buffer.set(message);
+        self.retry_buffer.set(batch);
         self.retry_buffer.increment_error_count();
     }
 
+    /// Exports messages in order from the spool. Messages received from the
+    /// collectors are moved to the spool before every export attempt so that
+    /// they are not held in memory while exporting fails.
+    fn start_with_spool(&mut self, mut spool: SpoolItem, receiver: Receiver<Message>) {
+        info!(
+            "Exporting through spool with {} buffered messages",
+            spool.spool.depth()
+        );
+
+        loop {
+            let mut disconnected = false;
+            if spool.spool.is_empty() {
+                match receiver.recv() {
+                    Ok(message) => spool.push(&message),
+                    Err(_) => break,
+                }
+
+                let deadline = Instant::now() + self.batch_max_latency;
+                while spool.spool.depth() < self.batch_size as u64 {
+                    match receiver.recv_deadline(deadline) {
+                        Ok(message) => spool.push(&message),
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => {
+                            disconnected = true;
+                            break;
+                        }
+                    }
+                }
+            }
+
+            while !disconnected {
+                match receiver.try_recv() {
+                    Ok(message) => spool.push(&message),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        disconnected = true;
+                        break;
+                    }
+                }
+            }
+            spool.report();
+            spool.sync();
+
+            match spool.spool.peek(self.batch_size) {
+                Ok(batch) if batch.is_empty() => (),
+                Ok(batch) => match self.exporter.export_batch(&batch) {
+                    Ok(_) => {
+                        debug!("Exported {} messages", batch.len());
+                        self.retry_buffer.decrement_error_count();
+                        if let Err(err) = spool.spool.pop(batch.len()) {
+                            error!("{}", err);
+                        }
+                    }
+                    Err(err) => {
+                        error!("{}", err);
+                        self.retry_buffer.increment_error_count();
+                    }
+                },
+                Err(err) => {
+                    error!("Reading spool failed: {}", err);
+                    self.retry_buffer.increment_error_count();
+                }
+            }
+
+            if disconnected {
+                break;
+            }
+            self.backoff_sleep();
+        }
+
+        error!(
+            "Collectors disconnected. Stopping exporter with {} buffered messages.",
+            spool.spool.depth()
+        );
+    }
+
     fn backoff_sleep(&self) {
         if self.retry_buffer.error_count > 0 {
             let backoff = self.retry_buffer.backoff_duration(true);
@@ -87,10 +274,62 @@ impl ExporterScheduler {
 pub trait Exporter {
     /// Starts exporting a message in an exporter implementation.
     fn export(&self, msg: &Message) -> Result<()>;
+
+    /// Exports multiple messages at once. Exporters which support batched
+    /// writes override this and others export the messages one by one.
+    fn export_batch(&self, msgs: &[Message]) -> Result<()> {
+        msgs.iter().try_for_each(|msg| self.export(msg))
+    }
+}
+
+/// Spool of an exporter scheduler and its status reporting.
+struct SpoolItem {
+    spool: Spool,
+    name: String,
+    hostname: String,
+    last_report: Option<Instant>,
+}
+
+impl SpoolItem {
+    fn push(&mut self, message: &Message) {
+        if let Err(err) = self.spool.push(message) {
+            error!("Writing message to spool failed: {}", err);
+        }
+    }
+
+    fn sync(&mut self) {
+        if let Err(err) = self.spool.sync() {
+            error!("Syncing spool failed: {}", err);
+        }
+    }
+
+    /// Adds a message with the spool status to the spool periodically.
+    fn report(&mut self) {
+        if let Some(last_report) = self.last_report {
+            if last_report.elapsed() < SPOOL_REPORT_INTERVAL {
+                return;
+            }
+        }
+        self.last_report = Some(Instant::now());
+
+        let mut message = Message::new("export_queue");
+        message.insert_tag("queue", &self.name);
+        message.insert_tag("hostname", &self.hostname);
+        message.insert_metric("queue_depth", self.spool.depth());
+        message.insert_metric("queue_size", self.spool.size());
+        message.insert_metric("dropped_messages", self.spool.dropped());
+        self.push(&message);
+    }
+}
+
+fn open_spool(config: &SpoolConfig, name: &str) -> Result<Spool> {
+    let directory = Path::new(&config.directory).join(name);
+    Spool::open(&directory, config.max_size * 1024 * 1024, config.max_age)
+        .map_err(|e| e.set_source("export_spool"))
 }
 
 struct RetryItem {
-    message: Option<Message>,
+    batch: Option<Vec<Message>>,
     error_count: u64,
     max_backoff: Duration,
 }
@@ -98,18 +337,18 @@ struct RetryItem {
 impl RetryItem {
     fn new(max_backoff: Duration) -> Self {
         RetryItem {
-            message: None,
+            batch: None,
             error_count: 0,
             max_backoff,
         }
     }
 
-    fn take(&mut self) -> Option<Message> {
-        self.message.take()
+    fn take(&mut self) -> Option<Vec<Message>> {
+        self.batch.take()
     }
 
-    fn set(&mut self, message: Message) {
-        self.message = Some(message);
+    fn set(&mut self, batch: Vec<Message>) {
+        self.batch = Some(batch);
     }
 
     fn increment_error_count(&mut self) {
@@ -146,15 +385,22 @@ impl RetryItem {
     }
 }
 
-impl Configure for ExporterScheduler {
-    fn from_config(config: &UptionConfig) -> Self {
-        match config.exporters.exporter {
-            ExporterSelection::InfluxDb => match config.exporters.influxdb.version {
-                InfluxDbVersion::V1 => ExporterScheduler::new(InfluxDbv1::from_config(config)),
-                InfluxDbVersion::V2 => ExporterScheduler::new(InfluxDbv2::from_config(config)),
-            },
-            ExporterSelection::Stdout => ExporterScheduler::new(Stdout::new()),
-            ExporterSelection::Logger => ExporterScheduler::new(Logger::new()),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_received_in_batches() {
+        let mut scheduler = ExporterScheduler::new(Stdout::new());
+        scheduler.set_batching(2, Duration::from_millis(10));
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        for _ in 0..3 {
+            sender.send(Message::new("measurement")).unwrap();
         }
+
+        assert_eq!(scheduler.receive(&receiver).unwrap().len(), 2);
+        assert_eq!(scheduler.receive(&receiver).unwrap().len(), 1);
+        drop(sender);
+        assert!(scheduler.receive(&receiver).is_none());
     }
 }
@@ -0,0 +1,269 @@
+//! Prometheus exporter keeps the latest value of every metric and serves them
+//! in Prometheus text exposition format from an embedded HTTP server.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info};
+use tiny_http::{Header, Method, Response, Server};
+
+use super::Exporter;
+use crate::config::PrometheusConfig;
+use crate::error::{Error, Result};
+use crate::message::Message;
+
+const METRIC_PREFIX: &str = "uption";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub struct Prometheus {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Prometheus {
+    /// Starts the HTTP server which serves the metrics from `/metrics` path.
+    /// Series which have not been updated within `expiry` are removed.
+    pub fn new(listen_address: SocketAddr, expiry: Duration) -> Result<Self> {
+        let server = Server::http(listen_address).map_err(|e| {
+            Error::new("Failed to start Prometheus exporter HTTP server")
+                .set_context(&format!("{}: {}", listen_address, e))
+        })?;
+        info!(
+            "Serving Prometheus metrics at http://{}/metrics",
+            listen_address
+        );
+
+        let registry = Arc::new(Mutex::new(Registry::new(expiry)));
+        let server_registry = Arc::clone(&registry);
+        thread::Builder::new()
+            .name("prometheus_server".into())
+            .spawn(move || serve(server, server_registry))
+            .map_err(|e| {
+                Error::new("Failed to start Prometheus exporter HTTP server").set_cause(e)
+            })?;
+
+        Ok(Prometheus { registry })
+    }
+}
+
+impl Exporter for Prometheus {
+    fn export(&self, msg: &Message) -> Result<()> {
+        self.registry.lock().unwrap().update(msg);
+        Ok(())
+    }
+}
+
+impl TryFrom<&PrometheusConfig> for Prometheus {
+    type Error = Error;
+
+    fn try_from(config: &PrometheusConfig) -> Result<Self> {
+        Prometheus::new(config.listen_address, Duration::from_secs(config.expiry))
+    }
+}
+
+fn serve(server: Server, registry: Arc<Mutex<Registry>>) {
+    for request in server.incoming_requests() {
+        debug!(
+            "Prometheus exporter request: {} {}",
+            request.method(),
+            request.url()
+        );
+        let response = if *request.method() == Method::Get && request.url() == "/metrics" {
+            let body = registry.lock().unwrap().render();
+            Response::from_string(body)
+                .with_header(Header::from_bytes("Content-Type", CONTENT_TYPE).unwrap())
+        } else {
+            Response::from_string("Not Found").with_status_code(404)
+        };
+
+        if let Err(err) = request.respond(response) {
+            error!("Failed to respond to Prometheus scrape: {}", err);
+        }
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+struct Series {
+    value: f64,
+    updated: Instant,
+}
+
+/// Latest values of all the series grouped by metric family.
+struct Registry {
+    families: BTreeMap<String, BTreeMap<Labels, Series>>,
+    expiry: Duration,
+}
+
+impl Registry {
+    fn new(expiry: Duration) -> Self {
+        Registry {
+            families: BTreeMap::new(),
+            expiry,
+        }
+    }
+
+    /// Updates series from the numeric metrics of a message. String metrics
+    /// can't be represented as Prometheus samples and they are skipped.
+    fn update(&mut self, msg: &Message) {
+        let labels: Labels = msg
+            .tags()
+            .iter()
+            .map(|(key, value)| (sanitize_name(key), value.to_owned()))
+            .collect();
+
+        for (name, value) in msg.metrics().iter() {
+            let Some(value) = value.as_f64() else {
+                continue;
+            };
+            let family = sanitize_name(&format!("{}_{}_{}", METRIC_PREFIX, msg.source(), name));
+            self.families.entry(family).or_default().insert(
+                labels.clone(),
+                Series {
+                    value,
+                    updated: Instant::now(),
+                },
+            );
+        }
+    }
+
+    fn expire(&mut self) {
+        let expiry = self.expiry;
+        for series in self.families.values_mut() {
+            series.retain(|_, series| series.updated.elapsed() < expiry);
+        }
+        self.families.retain(|_, series| !series.is_empty());
+    }
+
+    /// Renders all the series in text exposition format.
+    fn render(&mut self) -> String {
+        self.expire();
+
+        let mut output = String::new();
+        for (family, series) in self.families.iter() {
+            writeln!(output, "# TYPE {} gauge", family).unwrap();
+            for (labels, series) in series.iter() {
+                output.push_str(family);
+                if !labels.is_empty() {
+                    let labels: Vec<String> = labels
+                        .iter()
+                        .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+                        .collect();
+                    write!(output, "{{{}}}", labels.join(",")).unwrap();
+                }
+                writeln!(output, " {}", format_value(series.value)).unwrap();
+            }
+        }
+        output
+    }
+}
+
+/// Replaces characters which are not allowed in metric and label names with
+/// underscores.
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() && value > 0.0 {
+        "+Inf".to_string()
+    } else if value.is_infinite() {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::blocking::Client;
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    fn message() -> Message {
+        let mut message = Message::new("ping");
+        message.insert_tag("host", "example.com");
+        message.insert_tag("hostname", "uption \"1\"");
+        message.insert_metric("latency", 1.5);
+        message.insert_metric("packets_sent", 3u16);
+        message.insert_metric("error", "text");
+        message
+    }
+
+    #[rstest]
+    fn render_latest_values(message: Message) {
+        let mut registry = Registry::new(Duration::from_secs(60));
+        registry.update(&message);
+        let mut message = message;
+        message.insert_metric("latency", 2.5);
+        registry.update(&message);
+
+        assert_eq!(
+            registry.render(),
+            "# TYPE uption_ping_latency gauge\n\
+             uption_ping_latency{host=\"example.com\",hostname=\"uption \\\"1\\\"\"} 2.5\n\
+             # TYPE uption_ping_packets_sent gauge\n\
+             uption_ping_packets_sent{host=\"example.com\",hostname=\"uption \\\"1\\\"\"} 3\n"
+        );
+    }
+
+    #[rstest]
+    fn expired_series_are_removed(message: Message) {
+        let mut registry = Registry::new(Duration::ZERO);
+        registry.update(&message);
+
+        assert_eq!(registry.render(), "");
+    }
+
+    #[rstest]
+    #[case("wireless_station", "wireless_station")]
+    #[case("tx-bitrate.max", "tx_bitrate_max")]
+    #[case("5ghz", "_5ghz")]
+    fn sanitize_names(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(sanitize_name(name), expected);
+    }
+
+    #[rstest]
+    fn serve_metrics(message: Message) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let registry = Arc::new(Mutex::new(Registry::new(Duration::from_secs(60))));
+        registry.lock().unwrap().update(&message);
+        let server_registry = Arc::clone(&registry);
+        thread::spawn(move || serve(server, server_registry));
+
+        let client = Client::new();
+        let resp = client
+            .get(format!("http://{}/metrics", addr))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(resp.text().unwrap().contains("uption_ping_latency{"));
+
+        let resp = client.get(format!("http://{}/", addr)).send().unwrap();
+        assert_eq!(resp.status(), 404);
+    }
+}
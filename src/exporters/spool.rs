@@ -0,0 +1,523 @@
+//! Disk-backed queue which buffers messages between collectors and an
+//! exporter. Messages are appended as JSON lines to segment files and a
+//! separate cursor file records the position of the next unexported message,
+//! so that buffered messages survive restarts.
+//!
+//! Appended messages are synced to the disk by `sync`. The cursor is persisted
+//! only periodically, on segment boundaries and when the spool is closed to
+//! spare flash storage from a write on every exported batch. Messages which
+//! were exported after the cursor was last persisted are exported again after
+//! a crash.
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::warn;
+
+use crate::error::{Error, Result};
+use crate::message::Message;
+
+const CURSOR_FILE: &str = "cursor";
+const SEGMENT_EXTENSION: &str = "log";
+const SEGMENTS_PER_SPOOL: u64 = 10;
+const MIN_SEGMENT_SIZE: u64 = 64 * 1024;
+const CURSOR_PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Segment {
+    id: u64,
+    size: u64,
+}
+
+/// Position of the next unexported message.
+#[derive(Debug, PartialEq)]
+struct Cursor {
+    segment: u64,
+    offset: u64,
+}
+
+pub struct Spool {
+    directory: PathBuf,
+    max_size: u64,
+    max_age: chrono::Duration,
+    segment_size: u64,
+    segments: VecDeque<Segment>,
+    writer: File,
+    /// Whether messages have been appended since the writer was synced.
+    unsynced: bool,
+    reader: Option<BufReader<File>>,
+    read_offset: u64,
+    cursor: Cursor,
+    cursor_dirty: bool,
+    cursor_persisted: Instant,
+    head: VecDeque<(Message, u64)>,
+    depth: u64,
+    dropped: u64,
+}
+
+impl Spool {
+    /// Opens the spool from the directory or creates a new spool if the
+    /// directory does not contain one. `max_size` is in bytes and `max_age`
+    /// in seconds.
+    pub fn open(directory: &Path, max_size: u64, max_age: u64) -> Result<Self> {
+        fs::create_dir_all(directory).map_err(|e| {
+            Error::new("Failed to create spool directory")
+                .set_cause(e)
+                .set_context(&directory.display().to_string())
+        })?;
+
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        if ids.is_empty() {
+            ids.push(0);
+        }
+
+        let mut cursor = read_cursor(directory).unwrap_or(Cursor {
+            segment: ids[0],
+            offset: 0,
+        });
+        if cursor.segment < ids[0] || !ids.contains(&cursor.segment) {
+            cursor = Cursor {
+                segment: ids[0],
+                offset: 0,
+            };
+        }
+
+        let mut segments = VecDeque::new();
+        let mut depth = 0;
+        for id in ids {
+            let path = segment_path(directory, id);
+            if id < cursor.segment {
+                // Segment was consumed but not removed before shutdown.
+                fs::remove_file(&path)?;
+                continue;
+            }
+            let mut file = OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(&path)?;
+            let mut size = file.metadata()?.len();
+
+            // Terminate a line which was only partially written before a crash
+            // so that the following messages are not appended to it.
+            if size > 0 {
+                let mut last = [0; 1];
+                file.seek(SeekFrom::End(-1))?;
+                file.read_exact(&mut last)?;
+                if last[0] != b'\n' {
+                    file.write_all(b"\n")?;
+                    file.sync_data()?;
+                    size += 1;
+                }
+            }
+
+            let offset = if id == cursor.segment {
+                cursor.offset
+            } else {
+                0
+            };
+            depth += count_lines(&path, offset)?;
+            segments.push_back(Segment { id, size });
+        }
+
+        let last = segments.back().map(|segment| segment.id).unwrap();
+        let writer = OpenOptions::new()
+            .append(true)
+            .open(segment_path(directory, last))?;
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            max_size,
+            max_age: chrono::Duration::seconds(max_age as i64),
+            segment_size: (max_size / SEGMENTS_PER_SPOOL).max(MIN_SEGMENT_SIZE),
+            segments,
+            writer,
+            unsynced: false,
+            reader: None,
+            read_offset: cursor.offset,
+            cursor,
+            cursor_dirty: false,
+            cursor_persisted: Instant::now(),
+            head: VecDeque::new(),
+            depth,
+            dropped: 0,
+        })
+    }
+
+    /// Number of messages waiting in the spool.
+    pub fn depth(&self) -> u64 {
+        self.depth
+    }
+
+    /// Number of messages dropped because of size or age limits since the
+    /// spool was opened.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn size(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.size).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.depth == 0
+    }
+
+    /// Appends a message to the end of the spool. Oldest messages are dropped
+    /// if the spool exceeds its maximum size.
+    pub fn push(&mut self, message: &Message) -> Result<()> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+
+        if self.segments.back().unwrap().size >= self.segment_size {
+            self.rotate()?;
+        }
+        self.writer.write_all(&line)?;
+        self.unsynced = true;
+        self.segments.back_mut().unwrap().size += line.len() as u64;
+        self.depth += 1;
+
+        while self.size() > self.max_size && self.segments.len() > 1 {
+            self.drop_oldest_segment()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the appended messages to the disk and persists the cursor if it
+    /// has not been persisted for a while.
+    pub fn sync(&mut self) -> Result<()> {
+        if self.unsynced {
+            self.writer.sync_data()?;
+            self.unsynced = false;
+        }
+        if self.cursor_dirty && self.cursor_persisted.elapsed() >= CURSOR_PERSIST_INTERVAL {
+            self.persist_cursor()?;
+        }
+        Ok(())
+    }
+
+    /// Returns up to `max` oldest messages from the spool without removing
+    /// them. Messages exceeding the maximum age and unreadable messages are
+    /// dropped.
+    pub fn peek(&mut self, max: usize) -> Result<Vec<Message>> {
+        while self.head.len() < max {
+            let reader = match self.reader.as_mut() {
+                Some(reader) => reader,
+                None => {
+                    let mut file = File::open(segment_path(&self.directory, self.cursor.segment))?;
+                    file.seek(SeekFrom::Start(self.read_offset))?;
+                    self.reader.insert(BufReader::new(file))
+                }
+            };
+
+            let mut line = String::new();
+            let len = reader.read_line(&mut line)? as u64;
+            if len == 0 {
+                if !self.head.is_empty() || self.segments.len() == 1 {
+                    break;
+                }
+                let consumed = self.segments.pop_front().unwrap();
+                self.reader = None;
+                self.read_offset = 0;
+                self.set_cursor(self.segments[0].id, 0)?;
+                fs::remove_file(segment_path(&self.directory, consumed.id))?;
+                continue;
+            }
+            self.read_offset += len;
+
+            match serde_json::from_str::<Message>(&line) {
+                Ok(message) if *message.timestamp() < Utc::now() - self.max_age => {
+                    self.drop_read_message()?;
+                }
+                Ok(message) => self.head.push_back((message, self.read_offset)),
+                Err(err) => {
+                    warn!("Dropping unreadable message from spool: {}", err);
+                    self.drop_read_message()?;
+                }
+            }
+        }
+
+        Ok(self
+            .head
+            .iter()
+            .take(max)
+            .map(|(message, _)| message.clone())
+            .collect())
+    }
+
+    /// Removes `count` messages returned by the previous `peek` call.
+    pub fn pop(&mut self, count: usize) -> Result<()> {
+        let count = count.min(self.head.len());
+        let Some((_, next_offset)) = self.head.drain(..count).next_back() else {
+            return Ok(());
+        };
+        self.depth -= count as u64;
+
+        let next_offset = if self.head.is_empty() {
+            self.read_offset
+        } else {
+            next_offset
+        };
+        self.set_cursor(self.cursor.segment, next_offset)
+    }
+
+    fn drop_read_message(&mut self) -> Result<()> {
+        self.dropped += 1;
+        self.depth = self.depth.saturating_sub(1);
+        if self.head.is_empty() {
+            self.set_cursor(self.cursor.segment, self.read_offset)?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let id = self.segments.back().unwrap().id + 1;
+        if self.unsynced {
+            self.writer.sync_data()?;
+            self.unsynced = false;
+        }
+        self.writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&self.directory, id))?;
+        sync_directory(&self.directory)?;
+        self.segments.push_back(Segment { id, size: 0 });
+        Ok(())
+    }
+
+    fn drop_oldest_segment(&mut self) -> Result<()> {
+        let segment = self.segments.pop_front().unwrap();
+        let path = segment_path(&self.directory, segment.id);
+        let dropped = count_lines(&path, self.cursor.offset)?;
+        warn!(
+            "Spool maximum size exceeded, dropping {} oldest messages",
+            dropped
+        );
+        self.dropped += dropped;
+        self.depth = self.depth.saturating_sub(dropped);
+
+        self.head.clear();
+        self.reader = None;
+        self.read_offset = 0;
+        self.set_cursor(self.segments[0].id, 0)?;
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Moves the cursor. The cursor is persisted immediately when it moves to
+    /// another segment because the previous segment is removed after this.
+    fn set_cursor(&mut self, segment: u64, offset: u64) -> Result<()> {
+        let next_segment = segment != self.cursor.segment;
+        self.cursor = Cursor { segment, offset };
+        self.cursor_dirty = true;
+        if next_segment {
+            self.persist_cursor()?;
+        }
+        Ok(())
+    }
+
+    /// Persists the cursor by atomically replacing the cursor file.
+    fn persist_cursor(&mut self) -> Result<()> {
+        let tmp_path = self.directory.join(format!("{}.tmp", CURSOR_FILE));
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(format!("{} {}\n", self.cursor.segment, self.cursor.offset).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(tmp_path, self.directory.join(CURSOR_FILE))?;
+        sync_directory(&self.directory)?;
+        self.cursor_dirty = false;
+        self.cursor_persisted = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if self.unsynced {
+            if let Err(err) = self.writer.sync_data() {
+                warn!("Syncing spool failed: {}", err);
+            }
+        }
+        if self.cursor_dirty {
+            if let Err(err) = self.persist_cursor() {
+                warn!("Persisting spool cursor failed: {}", err);
+            }
+        }
+    }
+}
+
+/// Syncs directory entries so that created, removed and renamed files persist.
+fn sync_directory(directory: &Path) -> Result<()> {
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+fn segment_path(directory: &Path, id: u64) -> PathBuf {
+    directory.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+}
+
+fn read_cursor(directory: &Path) -> Option<Cursor> {
+    let content = fs::read_to_string(directory.join(CURSOR_FILE)).ok()?;
+    let mut parts = content.split_whitespace();
+    let segment = parts.next()?.parse().ok()?;
+    let offset = parts.next()?.parse().ok()?;
+    Some(Cursor { segment, offset })
+}
+
+fn count_lines(path: &Path, offset: u64) -> Result<u64> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut count = 0;
+    for line in BufReader::new(file).split(b'\n') {
+        line?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn message(value: u64) -> Message {
+        let mut message = Message::new("measurement");
+        message.insert_metric("value", value);
+        message
+    }
+
+    fn value(message: &Message) -> String {
+        message.metrics()["value"].to_string()
+    }
+
+    #[test]
+    fn messages_are_returned_in_order() {
+        let directory = TempDir::new("spool");
+        let mut spool = Spool::open(directory.path(), 1024 * 1024, 3600).unwrap();
+        spool.push(&message(1)).unwrap();
+        spool.push(&message(2)).unwrap();
+
+        assert_eq!(spool.depth(), 2);
+        assert_eq!(value(&spool.peek(1).unwrap()[0]), "1");
+        assert_eq!(value(&spool.peek(1).unwrap()[0]), "1");
+        spool.pop(1).unwrap();
+        assert_eq!(value(&spool.peek(1).unwrap()[0]), "2");
+        spool.pop(1).unwrap();
+        assert!(spool.peek(1).unwrap().is_empty());
+        assert!(spool.is_empty());
+    }
+
+    #[test]
+    fn batch_is_removed_at_once() {
+        let directory = TempDir::new("spool");
+        let mut spool = Spool::open(directory.path(), 1024 * 1024, 3600).unwrap();
+        for i in 0..5 {
+            spool.push(&message(i)).unwrap();
+        }
+
+        assert_eq!(spool.peek(3).unwrap().len(), 3);
+        spool.pop(3).unwrap();
+        assert_eq!(spool.depth(), 2);
+        let messages = spool.peek(3).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(value(&messages[0]), "3");
+    }
+
+    #[test]
+    fn messages_survive_reopening() {
+        let directory = TempDir::new("spool");
+        let mut spool = Spool::open(directory.path(), 1024 * 1024, 3600).unwrap();
+        for i in 0..3 {
+            spool.push(&message(i)).unwrap();
+        }
+        spool.peek(1).unwrap();
+        spool.pop(1).unwrap();
+        drop(spool);
+
+        let mut spool = Spool::open(directory.path(), 1024 * 1024, 3600).unwrap();
+        assert_eq!(spool.depth(), 2);
+        assert_eq!(value(&spool.peek(1).unwrap()[0]), "1");
+    }
+
+    #[test]
+    fn cursor_is_persisted_when_closed() {
+        let directory = TempDir::new("spool");
+        let mut spool = Spool::open(directory.path(), 1024 * 1024, 3600).unwrap();
+        for i in 0..3 {
+            spool.push(&message(i)).unwrap();
+        }
+        spool.sync().unwrap();
+        spool.peek(1).unwrap();
+        spool.pop(1).unwrap();
+        spool.sync().unwrap();
+
+        assert_eq!(read_cursor(directory.path()), None);
+        let offset = spool.read_offset;
+        drop(spool);
+        assert_eq!(
+            read_cursor(directory.path()),
+            Some(Cursor { segment: 0, offset })
+        );
+    }
+
+    #[test]
+    fn partially_written_message_is_dropped() {
+        let directory = TempDir::new("spool");
+        let mut spool = Spool::open(directory.path(), 1024 * 1024, 3600).unwrap();
+        spool.push(&message(1)).unwrap();
+        spool.writer.write_all(b"{\"timesta").unwrap();
+        drop(spool);
+
+        let mut spool = Spool::open(directory.path(), 1024 * 1024, 3600).unwrap();
+        spool.push(&message(2)).unwrap();
+        let messages = spool.peek(10).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(value(&messages[0]), "1");
+        assert_eq!(value(&messages[1]), "2");
+        assert_eq!(spool.dropped(), 1);
+    }
+
+    #[test]
+    fn oldest_messages_are_dropped_when_full() {
+        let directory = TempDir::new("spool");
+        let mut spool = Spool::open(directory.path(), 2 * MIN_SEGMENT_SIZE, 3600).unwrap();
+        for i in 0..10000 {
+            spool.push(&message(i)).unwrap();
+        }
+
+        assert!(spool.size() <= 2 * MIN_SEGMENT_SIZE);
+        assert!(spool.dropped() > 0);
+        assert_eq!(spool.depth() + spool.dropped(), 10000);
+        assert_eq!(
+            value(&spool.peek(1).unwrap()[0]),
+            spool.dropped().to_string()
+        );
+    }
+
+    #[test]
+    fn expired_messages_are_dropped() {
+        let directory = TempDir::new("spool");
+        let mut spool = Spool::open(directory.path(), 1024 * 1024, 0).unwrap();
+        spool.push(&message(1)).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        assert!(spool.peek(1).unwrap().is_empty());
+        assert_eq!(spool.dropped(), 1);
+        assert!(spool.is_empty());
+    }
+}
@@ -4,26 +4,96 @@ mod error;
 mod exporters;
 mod logging;
 mod message;
+mod net;
+#[cfg(test)]
+mod test_util;
 mod uption;
 mod url;
 
+use std::path::PathBuf;
 use std::process;
 
+use clap::{Parser, Subcommand};
 use log::{error, warn};
 
 use crate::config::UptionConfig;
 use uption::Uption;
 
+/// A tool to collect and export network performance metrics on Linux devices.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Configuration file. Defaults to /etc/uption/uption.toml if it exists
+    /// and to ./uption.toml otherwise.
+    #[arg(short, long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Collect and export metrics continuously (default).
+    Run,
+    /// Validate configuration and print the resolved settings with secrets
+    /// redacted.
+    Check,
+    /// Run every collector once, export the messages and exit. Exits with
+    /// status 2 if any collection failed and 3 if any export failed.
+    Once,
+    /// Print the default configuration.
+    PrintDefaultConfig,
+}
+
 fn main() {
-    set_ctrl_c_handler();
+    let cli = Cli::parse();
 
-    let config = UptionConfig::new().unwrap_or_else(|err| {
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => {
+            set_ctrl_c_handler();
+            let uption = Uption::new(load_config(&cli.config));
+            if let Err(err) = uption.start() {
+                error!("{}", err);
+                process::exit(1);
+            }
+        }
+        Command::Check => {
+            let config = load_config(&cli.config);
+            print_config(&config);
+        }
+        Command::Once => {
+            set_ctrl_c_handler();
+            let uption = Uption::new(load_config(&cli.config));
+            match uption.run_once() {
+                Ok((_, export_failures)) if export_failures > 0 => process::exit(3),
+                Ok((collection_failures, _)) if collection_failures > 0 => process::exit(2),
+                Ok(_) => (),
+                Err(err) => {
+                    error!("{}", err);
+                    process::exit(1);
+                }
+            }
+        }
+        Command::PrintDefaultConfig => print_config(&UptionConfig::default()),
+    }
+}
+
+fn load_config(path: &Option<PathBuf>) -> UptionConfig {
+    UptionConfig::new(path.as_deref()).unwrap_or_else(|err| {
         println!("Configuration error: {}", err);
         process::exit(1);
-    });
+    })
+}
 
-    let uption = Uption::new(config);
-    uption.start();
+fn print_config(config: &UptionConfig) {
+    match config.to_toml() {
+        Ok(config) => print!("{}", config),
+        Err(err) => {
+            println!("Configuration error: {}", err);
+            process::exit(1);
+        }
+    }
 }
 
 fn set_ctrl_c_handler() {
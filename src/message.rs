@@ -4,9 +4,9 @@ use std::collections::BTreeMap;
 use std::fmt;
 
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message {
     timestamp: DateTime<Utc>,
     source: String,
@@ -49,7 +49,7 @@ impl Message {
     }
 }
 
-#[derive(Serialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum PayloadValue {
     String(String),
     Int8(i8),
@@ -66,6 +66,28 @@ pub enum PayloadValue {
     Float64(f64),
 }
 
+impl PayloadValue {
+    /// Converts numeric values to a float. Returns `None` for strings.
+    pub fn as_f64(&self) -> Option<f64> {
+        let value = match self {
+            PayloadValue::String(_) => return None,
+            PayloadValue::Int8(val) => f64::from(*val),
+            PayloadValue::Int16(val) => f64::from(*val),
+            PayloadValue::Int32(val) => f64::from(*val),
+            PayloadValue::Int64(val) => *val as f64,
+            PayloadValue::Int128(val) => *val as f64,
+            PayloadValue::Uint8(val) => f64::from(*val),
+            PayloadValue::Uint16(val) => f64::from(*val),
+            PayloadValue::Uint32(val) => f64::from(*val),
+            PayloadValue::Uint64(val) => *val as f64,
+            PayloadValue::Uint128(val) => *val as f64,
+            PayloadValue::Float32(val) => f64::from(*val),
+            PayloadValue::Float64(val) => *val,
+        };
+        Some(value)
+    }
+}
+
 impl From<String> for PayloadValue {
     fn from(item: String) -> Self {
         PayloadValue::String(item)
@@ -0,0 +1,194 @@
+//! DNS message exchange over UDP, TCP, TLS (RFC 7858) and HTTPS (RFC 8484).
+//! Messages are passed as encoded bytes and connection setup is timed
+//! separately from the query.
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use super::http::Request;
+use super::tls::{self, TlsOptions};
+use super::{set_timeouts, IpFamily};
+use crate::error::{Error, ErrorKind, Result};
+use crate::url::{Host, HttpUrl};
+
+const MAX_MESSAGE_SIZE: usize = 65535;
+const CONTENT_TYPE: &str = "application/dns-message";
+/// ALPN protocol of DNS-over-TLS (RFC 7858).
+const DOT_ALPN_PROTOCOLS: &[&[u8]] = &[b"dot"];
+
+/// Response of a DNS query.
+#[derive(Debug)]
+pub struct Exchange {
+    pub response: Vec<u8>,
+    /// Time to set up the connection. UDP queries don't have a setup phase.
+    pub connect: Option<Duration>,
+    /// Time from sending the query to receiving the response.
+    pub query: Duration,
+}
+
+pub fn udp(server: SocketAddr, query: &[u8], timeout: Duration) -> Result<Exchange> {
+    let bind_addr: SocketAddr = match server {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.connect(server)?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    let start = Instant::now();
+    socket.send(query)?;
+    let mut buf = vec![0; MAX_MESSAGE_SIZE];
+    let len = socket.recv(&mut buf)?;
+    buf.truncate(len);
+    Ok(Exchange {
+        response: buf,
+        connect: None,
+        query: start.elapsed(),
+    })
+}
+
+pub fn tcp(server: SocketAddr, query: &[u8], timeout: Duration) -> Result<Exchange> {
+    let deadline = Instant::now() + timeout;
+    let connect_start = Instant::now();
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    let connect = connect_start.elapsed();
+    set_timeouts(&stream, deadline)?;
+
+    let start = Instant::now();
+    let response = exchange_stream(&mut stream, query)?;
+    Ok(Exchange {
+        response,
+        connect: Some(connect),
+        query: start.elapsed(),
+    })
+}
+
+/// Makes a DNS-over-TLS query. The server certificate is verified against
+/// `name`.
+pub fn tls(server: SocketAddr, name: &Host, query: &[u8], timeout: Duration) -> Result<Exchange> {
+    let deadline = Instant::now() + timeout;
+    let connect_start = Instant::now();
+    let tcp = TcpStream::connect_timeout(&server, timeout)?;
+    set_timeouts(&tcp, deadline)?;
+    let options = TlsOptions {
+        alpn_protocols: DOT_ALPN_PROTOCOLS,
+        ..Default::default()
+    };
+    let (mut stream, _) = tls::handshake(name, tcp, options)?;
+    let connect = connect_start.elapsed();
+    set_timeouts(stream.get_ref(), deadline)?;
+
+    let start = Instant::now();
+    let response = exchange_stream(&mut stream, query)?;
+    Ok(Exchange {
+        response,
+        connect: Some(connect),
+        query: start.elapsed(),
+    })
+}
+
+/// Makes a DNS-over-HTTPS query with a POST request. Name resolution of the
+/// server is included in the connection setup.
+pub fn https(
+    url: &HttpUrl,
+    family: Option<IpFamily>,
+    query: &[u8],
+    timeout: Duration,
+) -> Result<Exchange> {
+    let resp = Request::new("POST", url, timeout)
+        .header("Content-Type", CONTENT_TYPE)
+        .header("Accept", CONTENT_TYPE)
+        .body(query)
+        .ip_family(family)
+        .send()?;
+    if resp.status != 200 {
+        return Err(Error::new("Unsuccessful DNS-over-HTTPS status code")
+            .set_kind(ErrorKind::HttpStatus)
+            .set_context(&resp.status.to_string()));
+    }
+
+    let timings = resp.timings;
+    Ok(Exchange {
+        response: resp.body,
+        connect: Some(timings.dns + timings.connect + timings.tls.unwrap_or_default()),
+        query: timings.send + timings.ttfb + timings.receive,
+    })
+}
+
+/// Sends a message with a two byte length prefix and reads the response which
+/// is prefixed the same way.
+fn exchange_stream(stream: &mut (impl Read + Write), query: &[u8]) -> Result<Vec<u8>> {
+    let len = u16::try_from(query.len())
+        .map_err(|_| Error::new("DNS query is too large").set_kind(ErrorKind::Parse))?;
+    let mut message = Vec::with_capacity(query.len() + 2);
+    message.extend_from_slice(&len.to_be_bytes());
+    message.extend_from_slice(query);
+    stream.write_all(&message)?;
+    stream.flush()?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut response = vec![0; u16::from_be_bytes(len).into()];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn tcp_exchange() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut query = vec![0; u16::from_be_bytes(len).into()];
+            stream.read_exact(&mut query).unwrap();
+            stream.write_all(&[0, 3]).unwrap();
+            stream.write_all(&query[..3]).unwrap();
+        });
+
+        let exchange = tcp(addr, b"query", Duration::from_secs(1)).unwrap();
+
+        assert_eq!(exchange.response, b"que");
+        assert!(exchange.connect.is_some());
+    }
+
+    #[test]
+    fn https_exchange() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("POST", "/dns-query")
+            .match_header("content-type", CONTENT_TYPE)
+            .match_header("accept", CONTENT_TYPE)
+            .match_body("query")
+            .with_header("content-type", CONTENT_TYPE)
+            .with_body("response")
+            .create();
+        let url: HttpUrl = format!("{}/dns-query", server.url()).parse().unwrap();
+        let exchange = https(&url, None, b"query", Duration::from_secs(1)).unwrap();
+
+        assert_eq!(exchange.response, b"response");
+        assert!(exchange.connect.is_some());
+        m.assert();
+    }
+
+    #[test]
+    fn udp_exchange_timeout() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = udp(
+            socket.local_addr().unwrap(),
+            b"query",
+            Duration::from_millis(10),
+        )
+        .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+    }
+}
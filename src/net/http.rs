@@ -0,0 +1,704 @@
+//! Minimal HTTP/1.1 client which measures the duration of every phase of a
+//! request. Connections are never reused so that every request includes name
+//! resolution and the TCP and TLS handshakes.
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use super::tls::{self, TlsInfo, TlsOptions, TlsStream};
+use super::{remaining, resolve, DeadlineStream, IpFamily};
+use crate::error::{Error, ErrorKind, Result};
+use crate::url::HttpUrl;
+
+const USER_AGENT: &str = concat!("uption/", env!("CARGO_PKG_VERSION"));
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+/// Response bodies are read up to this size by default and the rest is
+/// ignored.
+const MAX_BODY_SIZE: u64 = 10 * 1024 * 1024;
+const BUFFER_SIZE: usize = 64 * 1024;
+const ALPN_PROTOCOLS: &[&[u8]] = &[b"http/1.1"];
+
+/// Durations of the different phases of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct Timings {
+    pub dns: Duration,
+    pub connect: Duration,
+    /// TLS handshake duration. Only HTTPS requests have it.
+    pub tls: Option<Duration>,
+    /// Time spent writing the request head and body.
+    pub send: Duration,
+    /// Time from sending the request to receiving the first byte of the
+    /// response.
+    pub ttfb: Duration,
+    /// Time spent reading the response body.
+    pub receive: Duration,
+    pub total: Duration,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    /// Response headers with lowercase names.
+    #[allow(dead_code)]
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Number of body bytes read. Body is not stored when it's discarded.
+    pub body_size: u64,
+    /// Number of request body bytes sent.
+    pub sent_body_size: u64,
+    pub remote_addr: SocketAddr,
+    pub timings: Timings,
+    /// TLS session details of HTTPS requests.
+    pub tls: Option<TlsInfo>,
+}
+
+/// Request body which is either given as it is or generated as zero bytes
+/// while it's sent.
+#[derive(Clone, Copy)]
+enum RequestBody<'a> {
+    Bytes(&'a [u8]),
+    Zeros {
+        size: u64,
+        max_time: Option<Duration>,
+    },
+}
+
+pub struct Request<'a> {
+    method: &'a str,
+    url: &'a HttpUrl,
+    timeout: Duration,
+    headers: Vec<(String, String)>,
+    body: Option<RequestBody<'a>>,
+    tls: TlsOptions<'a>,
+    family: Option<IpFamily>,
+    max_body_size: u64,
+    max_receive_time: Option<Duration>,
+    keep_body: bool,
+}
+
+impl<'a> Request<'a> {
+    pub fn new(method: &'a str, url: &'a HttpUrl, timeout: Duration) -> Self {
+        Self {
+            method,
+            url,
+            timeout,
+            headers: Vec::new(),
+            body: None,
+            tls: TlsOptions::default(),
+            family: None,
+            max_body_size: MAX_BODY_SIZE,
+            max_receive_time: None,
+            keep_body: true,
+        }
+    }
+
+    /// Adds a header to the request. Headers replace the default headers
+    /// with the same name except `Connection`, `Content-Length` and
+    /// `Transfer-Encoding` which are always set by the client.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn basic_auth(self, username: &str, password: &str) -> Self {
+        let credentials = BASE64_STANDARD.encode(format!("{}:{}", username, password));
+        self.header("Authorization", &format!("Basic {}", credentials))
+    }
+
+    pub fn bearer_auth(self, token: &str) -> Self {
+        self.header("Authorization", &format!("Bearer {}", token))
+    }
+
+    pub fn body(mut self, body: &'a [u8]) -> Self {
+        self.body = Some(RequestBody::Bytes(body));
+        self
+    }
+
+    /// Sends `size` zero bytes as a chunked body without keeping them in
+    /// memory. The body ends early when `max_time` has passed.
+    pub fn zero_body(mut self, size: u64, max_time: Option<Duration>) -> Self {
+        self.body = Some(RequestBody::Zeros { size, max_time });
+        self
+    }
+
+    /// Reads the response body until `size` bytes or `time` have been
+    /// reached and ignores the rest of it.
+    pub fn receive_limits(mut self, size: u64, time: Option<Duration>) -> Self {
+        self.max_body_size = size;
+        self.max_receive_time = time;
+        self
+    }
+
+    /// Counts response body bytes without storing them.
+    pub fn discard_body(mut self) -> Self {
+        self.keep_body = false;
+        self
+    }
+
+    /// Sets TLS options for HTTPS requests.
+    pub fn tls(mut self, options: TlsOptions<'a>) -> Self {
+        self.tls = options;
+        self
+    }
+
+    /// Connects only to addresses of the given family.
+    pub fn ip_family(mut self, family: Option<IpFamily>) -> Self {
+        self.family = family;
+        self
+    }
+
+    /// Sends the request and reads the response. The timeout applies to the
+    /// whole request except name resolution. Every read and write fails once
+    /// the timeout has passed.
+    pub fn send(&self) -> Result<Response> {
+        let request_head = self.request_head()?;
+        let start = Instant::now();
+        let deadline = start + self.timeout;
+
+        let host = self.url.host();
+        let remote_addr = SocketAddr::new(resolve(&host, self.family)?, self.url.port());
+        let dns = start.elapsed();
+
+        let connect_start = Instant::now();
+        let tcp = TcpStream::connect_timeout(&remote_addr, remaining(deadline)?)?;
+        let connect = connect_start.elapsed();
+        let stream = DeadlineStream::new(tcp, deadline);
+
+        let (mut connection, tls_time, tls) = if self.url.is_https() {
+            let tls_start = Instant::now();
+            let options = TlsOptions {
+                alpn_protocols: ALPN_PROTOCOLS,
+                ..self.tls
+            };
+            let (stream, info) = tls::handshake(&host, stream, options)?;
+            let tls_time = tls_start.elapsed();
+            (
+                Connection::Tls(Box::new(stream)),
+                Some(tls_time),
+                Some(info),
+            )
+        } else {
+            (Connection::Plain(stream), None, None)
+        };
+
+        let send_start = Instant::now();
+        connection.write_all(request_head.as_bytes())?;
+        let sent_body_size = match self.body {
+            Some(RequestBody::Bytes(body)) => {
+                for chunk in body.chunks(BUFFER_SIZE) {
+                    connection.write_all(chunk)?;
+                }
+                body.len() as u64
+            }
+            Some(RequestBody::Zeros { size, max_time }) => {
+                let deadline = max_time.map(|time| send_start + time);
+                write_zeros(&mut connection, size, deadline)?
+            }
+            None => 0,
+        };
+        connection.flush()?;
+        let request_sent = Instant::now();
+        let send = request_sent - send_start;
+
+        let mut reader = BufReader::new(connection);
+        if reader.fill_buf()?.is_empty() {
+            return Err(
+                Error::new("Connection closed before response").set_context(self.url.as_str())
+            );
+        }
+        let ttfb = request_sent.elapsed();
+
+        let (status, headers) = read_head(&mut reader)?;
+        let receive_start = Instant::now();
+        let mut body = Body {
+            data: Vec::new(),
+            size: 0,
+            max_size: self.max_body_size,
+            deadline: self.max_receive_time.map(|time| receive_start + time),
+            keep: self.keep_body,
+        };
+        let has_body = self.method != "HEAD" && status >= 200 && status != 204 && status != 304;
+        if has_body {
+            body.read(&mut reader, &headers)?;
+        }
+        let receive = receive_start.elapsed();
+
+        Ok(Response {
+            status,
+            headers,
+            body: body.data,
+            body_size: body.size,
+            sent_body_size,
+            remote_addr,
+            timings: Timings {
+                dns,
+                connect,
+                tls: tls_time,
+                send,
+                ttfb,
+                receive,
+                total: start.elapsed(),
+            },
+            tls,
+        })
+    }
+
+    fn request_head(&self) -> Result<String> {
+        let mut headers = vec![
+            ("Host", self.url.authority()),
+            ("User-Agent", USER_AGENT),
+            ("Accept", "*/*"),
+        ];
+        headers.retain(|(default, _)| {
+            !self
+                .headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case(default))
+        });
+        for (name, value) in self.headers.iter() {
+            if name.eq_ignore_ascii_case("connection")
+                || name.eq_ignore_ascii_case("content-length")
+                || name.eq_ignore_ascii_case("transfer-encoding")
+            {
+                continue;
+            }
+            if name.is_empty() || name.contains([':', '\r', '\n']) || value.contains(['\r', '\n']) {
+                return Err(Error::new("Invalid HTTP header").set_context(name));
+            }
+            headers.push((name, value));
+        }
+
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, self.url.request_target());
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("Connection: close\r\n");
+        match self.body {
+            Some(RequestBody::Bytes(body)) => {
+                head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+            }
+            Some(RequestBody::Zeros { .. }) => head.push_str("Transfer-Encoding: chunked\r\n"),
+            None => (),
+        }
+        head.push_str("\r\n");
+        Ok(head)
+    }
+}
+
+enum Connection {
+    Plain(DeadlineStream),
+    Tls(Box<TlsStream<DeadlineStream>>),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Writes `size` zero bytes in chunks and the last chunk which ends the body.
+/// Stops writing at the deadline and returns the number of bytes written.
+fn write_zeros(writer: &mut impl Write, size: u64, deadline: Option<Instant>) -> Result<u64> {
+    let chunk = |len: usize| {
+        let mut chunk = format!("{:x}\r\n", len).into_bytes();
+        chunk.resize(chunk.len() + len, 0);
+        chunk.extend_from_slice(b"\r\n");
+        chunk
+    };
+    let full_chunk = chunk(BUFFER_SIZE);
+    let mut written = 0;
+    while written < size && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        let len = (size - written).min(BUFFER_SIZE as u64) as usize;
+        if len == BUFFER_SIZE {
+            writer.write_all(&full_chunk)?;
+        } else {
+            writer.write_all(&chunk(len))?;
+        }
+        written += len as u64;
+    }
+    writer.write_all(b"0\r\n\r\n")?;
+    Ok(written)
+}
+
+/// Reads the status line and headers of a response.
+fn read_head(reader: &mut impl BufRead) -> Result<(u16, Vec<(String, String)>)> {
+    let mut reader = reader.take(MAX_HEAD_SIZE);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = parse_status_line(&line)?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::new("Incomplete HTTP response head").set_kind(ErrorKind::Parse));
+        }
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').ok_or_else(|| {
+            Error::new("Invalid HTTP header")
+                .set_kind(ErrorKind::Parse)
+                .set_context(header)
+        })?;
+        headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+    }
+    Ok((status, headers))
+}
+
+/// Response body which is read until its end or until the size or time limit
+/// is reached.
+struct Body {
+    data: Vec<u8>,
+    size: u64,
+    max_size: u64,
+    deadline: Option<Instant>,
+    keep: bool,
+}
+
+impl Body {
+    /// Reads a body which is delimited by chunked transfer encoding, content
+    /// length or the end of the connection.
+    fn read(&mut self, reader: &mut impl BufRead, headers: &[(String, String)]) -> Result<()> {
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        if header("transfer-encoding").is_some_and(|value| value.eq_ignore_ascii_case("chunked")) {
+            loop {
+                let mut line = String::new();
+                reader.take(MAX_HEAD_SIZE).read_line(&mut line)?;
+                let size = line.trim_end().split(';').next().unwrap_or_default();
+                let size = u64::from_str_radix(size.trim(), 16).map_err(|e| {
+                    Error::new("Invalid HTTP chunk size")
+                        .set_cause(e)
+                        .set_context(line.trim_end())
+                })?;
+                if size == 0 || !self.copy(reader, Some(size))? {
+                    break;
+                }
+                reader.read_line(&mut line)?;
+            }
+        } else if let Some(length) = header("content-length") {
+            let length = length.parse().map_err(|e| {
+                Error::new("Invalid HTTP content length")
+                    .set_cause(e)
+                    .set_context(length)
+            })?;
+            self.copy(reader, Some(length))?;
+        } else {
+            self.copy(reader, None)?;
+        }
+        Ok(())
+    }
+
+    /// Copies `length` bytes or until the end of the connection from the
+    /// reader. Returns false if a limit was reached before that.
+    fn copy(&mut self, reader: &mut impl Read, length: Option<u64>) -> Result<bool> {
+        let mut remaining = length.unwrap_or(u64::MAX);
+        let mut buffer = vec![0; BUFFER_SIZE];
+        while remaining > 0 {
+            let limit = remaining.min(self.max_size - self.size);
+            if limit == 0
+                || self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Ok(false);
+            }
+
+            let read = match reader.read(&mut buffer[..limit.min(BUFFER_SIZE as u64) as usize]) {
+                Ok(0) if length.is_none() => return Ok(true),
+                Ok(0) => {
+                    return Err(
+                        Error::new("Incomplete HTTP response body").set_kind(ErrorKind::Parse)
+                    )
+                }
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                // TLS connections may be closed without a close_notify alert
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && length.is_none() => {
+                    return Ok(true)
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if self.keep {
+                self.data.extend_from_slice(&buffer[..read]);
+            }
+            self.size += read as u64;
+            remaining -= read as u64;
+        }
+        Ok(true)
+    }
+}
+
+/// Parses status code from a status line such as `HTTP/1.1 200 OK`.
+fn parse_status_line(line: &str) -> Result<u16> {
+    let mut parts = line.trim_end().splitn(3, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/") => {
+            status.parse().map_err(|e| {
+                Error::new("Invalid HTTP status code")
+                    .set_cause(e)
+                    .set_context(line.trim_end())
+            })
+        }
+        _ => Err(Error::new("Invalid HTTP status line")
+            .set_kind(ErrorKind::Parse)
+            .set_context(line.trim_end())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("HTTP/1.1 200 OK\r\n", 200)]
+    #[case("HTTP/1.0 404 Not Found\r\n", 404)]
+    #[case("HTTP/1.1 204\r\n", 204)]
+    fn parse_valid_status_lines(#[case] line: &str, #[case] expected: u16) {
+        assert_eq!(parse_status_line(line).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("SSH-2.0-OpenSSH\r\n")]
+    #[case("HTTP/1.1 OK\r\n")]
+    fn parse_invalid_status_lines(#[case] line: &str) {
+        assert_eq!(
+            parse_status_line(line).unwrap_err().kind(),
+            ErrorKind::Parse
+        );
+    }
+
+    #[test]
+    fn read_response_head() {
+        let mut head =
+            "HTTP/1.1 301 Moved\r\nLocation: /new\r\nX-Custom:  a:b \r\n\r\nbody".as_bytes();
+        let (status, headers) = read_head(&mut head).unwrap();
+
+        assert_eq!(status, 301);
+        assert_eq!(
+            headers,
+            vec![
+                ("location".to_string(), "/new".to_string()),
+                ("x-custom".to_string(), "a:b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn send_request() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("HEAD", "/path?query=1")
+            .match_header("host", server.host_with_port().as_str())
+            .with_status(418)
+            .create();
+        let url: HttpUrl = format!("{}/path?query=1", server.url()).parse().unwrap();
+        let resp = Request::new("HEAD", &url, Duration::from_secs(1))
+            .send()
+            .unwrap();
+
+        assert_eq!(resp.status, 418);
+        assert_eq!(resp.remote_addr, server.socket_address());
+        assert!(resp.timings.tls.is_none());
+        assert!(resp.timings.total >= resp.timings.ttfb);
+        m.assert();
+    }
+
+    #[test]
+    fn send_request_with_body() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("POST", "/")
+            .match_header("content-type", "application/json")
+            .match_header("user-agent", "custom")
+            .match_header("content-length", "2")
+            .match_body("{}")
+            .with_body("response body")
+            .create();
+        let url: HttpUrl = server.url().parse().unwrap();
+        let resp = Request::new("POST", &url, Duration::from_secs(1))
+            .header("Content-Type", "application/json")
+            .header("User-Agent", "custom")
+            .header("Content-Length", "100")
+            .body(b"{}")
+            .send()
+            .unwrap();
+
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body, b"response body");
+        m.assert();
+    }
+
+    #[test]
+    fn send_zero_body() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("POST", "/")
+            .match_header("transfer-encoding", "chunked")
+            .match_body(vec![0; 100_000])
+            .with_status(204)
+            .create();
+        let url: HttpUrl = server.url().parse().unwrap();
+        let resp = Request::new("POST", &url, Duration::from_secs(1))
+            .zero_body(100_000, None)
+            .send()
+            .unwrap();
+
+        assert_eq!(resp.status, 204);
+        assert_eq!(resp.sent_body_size, 100_000);
+        m.assert();
+    }
+
+    #[test]
+    fn slow_response_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            // Every byte arrives well within the socket timeout
+            for _ in 0..100 {
+                if stream.write_all(b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let url: HttpUrl = format!("http://{}/", addr).parse().unwrap();
+        let start = Instant::now();
+        let err = Request::new("GET", &url, Duration::from_millis(300))
+            .send()
+            .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn write_zeros_until_deadline() {
+        let mut body = Vec::new();
+        let written = write_zeros(&mut body, 1_000_000, Some(Instant::now())).unwrap();
+
+        assert_eq!(written, 0);
+        assert_eq!(body, b"0\r\n\r\n");
+    }
+
+    #[rstest]
+    #[case(3, b"3\r\n\0\0\0\r\n0\r\n\r\n".to_vec())]
+    #[case(0, b"0\r\n\r\n".to_vec())]
+    fn write_zero_chunks(#[case] size: u64, #[case] expected: Vec<u8>) {
+        let mut body = Vec::new();
+
+        assert_eq!(write_zeros(&mut body, size, None).unwrap(), size);
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn invalid_header_is_rejected() {
+        let url: HttpUrl = "http://localhost/".parse().unwrap();
+        let err = Request::new("GET", &url, Duration::from_secs(1))
+            .header("X-Injected", "value\r\nHost: other")
+            .send()
+            .unwrap_err();
+
+        assert_eq!(err.context().as_deref(), Some("X-Injected"));
+    }
+
+    #[rstest]
+    #[case("Content-Length: 5\r\n", "hello world", "hello")]
+    #[case(
+        "Transfer-Encoding: chunked\r\n",
+        "5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        "hello world"
+    )]
+    #[case("", "until close", "until close")]
+    fn read_response_body(#[case] headers: &str, #[case] body: &str, #[case] expected: &str) {
+        let response = format!("HTTP/1.1 200 OK\r\n{}\r\n{}", headers, body);
+        let mut reader = response.as_bytes();
+        let (_, headers) = read_head(&mut reader).unwrap();
+        let mut body = Body {
+            data: Vec::new(),
+            size: 0,
+            max_size: MAX_BODY_SIZE,
+            deadline: None,
+            keep: true,
+        };
+        body.read(&mut reader, &headers).unwrap();
+
+        assert_eq!(String::from_utf8(body.data).unwrap(), expected);
+        assert_eq!(body.size, expected.len() as u64);
+    }
+
+    #[rstest]
+    #[case("Content-Length: 11\r\n", "hello world")]
+    #[case(
+        "Transfer-Encoding: chunked\r\n",
+        "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+    )]
+    fn read_response_body_with_size_limit(#[case] headers: &str, #[case] body: &str) {
+        let response = format!("HTTP/1.1 200 OK\r\n{}\r\n{}", headers, body);
+        let mut reader = response.as_bytes();
+        let (_, headers) = read_head(&mut reader).unwrap();
+        let mut body = Body {
+            data: Vec::new(),
+            size: 0,
+            max_size: 7,
+            deadline: None,
+            keep: false,
+        };
+        body.read(&mut reader, &headers).unwrap();
+
+        assert!(body.data.is_empty());
+        assert_eq!(body.size, 7);
+    }
+
+    #[test]
+    #[ignore]
+    fn send_https_request() {
+        let url: HttpUrl = "https://example.com/".parse().unwrap();
+        let resp = Request::new("HEAD", &url, Duration::from_secs(5))
+            .send()
+            .unwrap();
+
+        assert!(resp.timings.tls.is_some());
+    }
+}
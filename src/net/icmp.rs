@@ -0,0 +1,226 @@
+//! ICMP echo implementation. Unprivileged datagram ICMP sockets are used when
+//! `net.ipv4.ping_group_range` allows it and raw sockets otherwise.
+use std::io::{ErrorKind, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::error::{Error, Result};
+
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+pub(super) const ICMPV4_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+pub(super) const ICMPV6_ECHO_REPLY: u8 = 129;
+pub(super) const ICMP_HEADER_LEN: usize = 8;
+const PAYLOAD: &[u8; 56] = b"uption-ping-payload-uption-ping-payload-uption-ping-pay!";
+
+pub struct IcmpSocket {
+    socket: Socket,
+    addr: IpAddr,
+    kind: Type,
+    identifier: u16,
+}
+
+impl IcmpSocket {
+    /// Opens an ICMP socket connected to the given address. Port of the
+    /// address is ignored.
+    pub fn connect(target: SocketAddr) -> Result<Self> {
+        let addr = target.ip();
+        let (domain, protocol) = match addr {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+
+        let (socket, kind) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+            Ok(socket) => (socket, Type::DGRAM),
+            Err(_) => Socket::new(domain, Type::RAW, Some(protocol))
+                .map(|socket| (socket, Type::RAW))
+                .map_err(|e| {
+                    Error::new("Failed to open ICMP socket")
+                        .set_cause(e)
+                        .set_context("check net.ipv4.ping_group_range or CAP_NET_RAW")
+                })?,
+        };
+        socket.connect(&target.into())?;
+
+        Ok(Self {
+            socket,
+            addr,
+            kind,
+            identifier: rand::random(),
+        })
+    }
+
+    /// Sends an ICMP echo request with the given sequence number.
+    pub fn send_echo_request(&self, sequence: u16) -> Result<()> {
+        self.socket.send(&self.echo_request(sequence))?;
+        Ok(())
+    }
+
+    /// Waits for an ICMP echo reply to any of the sent requests. Returns the
+    /// sequence number of the reply or `None` if no reply was received before
+    /// the deadline.
+    pub fn recv_echo_reply(&self, deadline: Instant) -> Result<Option<u16>> {
+        let mut buf = [0; 1500];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(deadline - now))?;
+
+            let len = match (&self.socket).read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if let Some(sequence) = self.parse_echo_reply(&buf[..len]) {
+                return Ok(Some(sequence));
+            }
+        }
+    }
+
+    fn echo_request(&self, sequence: u16) -> Vec<u8> {
+        echo_request(self.addr, self.identifier, sequence)
+    }
+
+    fn parse_echo_reply(&self, packet: &[u8]) -> Option<u16> {
+        // Raw IPv4 sockets receive the IP header in front of the ICMP message.
+        let packet = match (self.addr, self.kind) {
+            (IpAddr::V4(_), Type::RAW) => {
+                let header_len = match packet.first() {
+                    Some(first) => usize::from(first & 0x0f) * 4,
+                    None => return None,
+                };
+                packet.get(header_len..)?
+            }
+            _ => packet,
+        };
+
+        if packet.len() < ICMP_HEADER_LEN {
+            return None;
+        }
+
+        let expected_type = match self.addr {
+            IpAddr::V4(_) => ICMPV4_ECHO_REPLY,
+            IpAddr::V6(_) => ICMPV6_ECHO_REPLY,
+        };
+        let identifier = u16::from_be_bytes([packet[4], packet[5]]);
+        let sequence = u16::from_be_bytes([packet[6], packet[7]]);
+
+        // Kernel replaces the identifier of datagram ICMP sockets and filters
+        // the replies by it.
+        let is_reply = packet[0] == expected_type
+            && (self.kind == Type::DGRAM || identifier == self.identifier);
+        is_reply.then_some(sequence)
+    }
+}
+
+/// Builds an ICMP echo request to the given address.
+pub(super) fn echo_request(addr: IpAddr, identifier: u16, sequence: u16) -> Vec<u8> {
+    let icmp_type = match addr {
+        IpAddr::V4(_) => ICMPV4_ECHO_REQUEST,
+        IpAddr::V6(_) => ICMPV6_ECHO_REQUEST,
+    };
+
+    let mut packet = Vec::with_capacity(ICMP_HEADER_LEN + PAYLOAD.len());
+    packet.extend_from_slice(&[icmp_type, 0, 0, 0]);
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(PAYLOAD);
+
+    // Kernel calculates the checksum for ICMPv6 because it depends on the
+    // IPv6 pseudo header.
+    if addr.is_ipv4() {
+        let checksum = checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    packet
+}
+
+/// Internet checksum as defined in RFC 1071.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|chunk| match chunk {
+            [high, low] => u32::from(u16::from_be_bytes([*high, *low])),
+            [high] => u32::from(u16::from_be_bytes([*high, 0])),
+            _ => unreachable!(),
+        })
+        .sum();
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket(kind: Type) -> IcmpSocket {
+        IcmpSocket {
+            socket: Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap(),
+            addr: "127.0.0.1".parse().unwrap(),
+            kind,
+            identifier: 0x1234,
+        }
+    }
+
+    fn reply(identifier: u16, sequence: u16) -> Vec<u8> {
+        let mut packet = vec![ICMPV4_ECHO_REPLY, 0, 0, 0];
+        packet.extend_from_slice(&identifier.to_be_bytes());
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn checksum_of_rfc1071_example() {
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&data), !0xddf2);
+    }
+
+    #[test]
+    fn echo_request_has_valid_checksum() {
+        let request = socket(Type::RAW).echo_request(7);
+
+        assert_eq!(request[0], ICMPV4_ECHO_REQUEST);
+        assert_eq!(&request[4..8], &[0x12, 0x34, 0x00, 0x07]);
+        assert_eq!(checksum(&request), 0);
+    }
+
+    #[test]
+    fn echo_reply_from_datagram_socket() {
+        let socket = socket(Type::DGRAM);
+
+        assert_eq!(socket.parse_echo_reply(&reply(0x9999, 7)), Some(7));
+    }
+
+    #[test]
+    fn echo_reply_from_raw_socket() {
+        let socket = socket(Type::RAW);
+        let mut packet = vec![0x45];
+        packet.extend_from_slice(&[0; 19]);
+        packet.extend_from_slice(&reply(0x1234, 7));
+
+        assert_eq!(socket.parse_echo_reply(&packet), Some(7));
+        packet[24] = 0x99;
+        assert_eq!(socket.parse_echo_reply(&packet), None);
+    }
+
+    #[test]
+    fn echo_request_is_not_reply() {
+        let socket = socket(Type::RAW);
+        let mut packet = vec![0x45];
+        packet.extend_from_slice(&[0; 19]);
+        packet.extend_from_slice(&socket.echo_request(7));
+
+        assert_eq!(socket.parse_echo_reply(&packet), None);
+    }
+}
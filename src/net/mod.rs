@@ -0,0 +1,180 @@
+//! Low level networking helpers which are shared between different collectors.
+pub mod dns;
+pub mod http;
+mod icmp;
+pub mod ntp;
+pub mod route;
+pub mod tls;
+pub mod traceroute;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use url::Host as UrlHost;
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::url::Host;
+pub use icmp::IcmpSocket;
+
+/// IP address family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn of(addr: &IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(_) => IpFamily::V4,
+            IpAddr::V6(_) => IpFamily::V6,
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "ipv4"),
+            IpFamily::V6 => write!(f, "ipv6"),
+        }
+    }
+}
+
+/// Resolves host to an IP address of the given family or of any family.
+/// Hosts which are already IP addresses are returned as is without making a
+/// DNS query and the default gateway is looked up from the routing table.
+pub fn resolve(host: &Host, family: Option<IpFamily>) -> Result<IpAddr> {
+    let matches = |addr: &IpAddr| family.is_none_or(|family| IpFamily::of(addr) == family);
+    let host = match host {
+        Host::Host(host) => host,
+        Host::Gateway => return route::default_gateway(family).map(|gateway| gateway.addr),
+    };
+    let addr = match host {
+        UrlHost::Ipv4(addr) => Some(IpAddr::V4(*addr)).filter(matches),
+        UrlHost::Ipv6(addr) => Some(IpAddr::V6(*addr)).filter(matches),
+        UrlHost::Domain(domain) => (domain.as_str(), 0)
+            .to_socket_addrs()
+            .map_err(|e| {
+                Error::new("Failed to resolve host")
+                    .set_kind(ErrorKind::DnsFailure)
+                    .set_cause(e)
+                    .set_context(domain)
+            })?
+            .map(|addr| addr.ip())
+            .find(matches),
+    };
+    addr.ok_or_else(|| {
+        let message = match family {
+            Some(IpFamily::V4) => "Host has no IPv4 addresses",
+            Some(IpFamily::V6) => "Host has no IPv6 addresses",
+            None => "Host resolved to no addresses",
+        };
+        Error::new(message)
+            .set_kind(ErrorKind::DnsFailure)
+            .set_context(&host.to_string())
+    })
+}
+
+/// Resolves host like [`resolve`] to a socket address with the given port and
+/// returns also the gateway when the host is the default gateway. Socket
+/// address of a link-local gateway has the scope of its interface.
+pub fn resolve_with_gateway(
+    host: &Host,
+    family: Option<IpFamily>,
+    port: u16,
+) -> Result<(SocketAddr, Option<route::Gateway>)> {
+    match host {
+        Host::Gateway => {
+            let gateway = route::default_gateway(family)?;
+            Ok((gateway.socket_addr(port), Some(gateway)))
+        }
+        Host::Host(_) => Ok((SocketAddr::new(resolve(host, family)?, port), None)),
+    }
+}
+
+/// Returns the time left until the deadline or a timeout error if it has
+/// passed.
+fn remaining(deadline: Instant) -> Result<Duration> {
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(Error::new("Request timed out").set_kind(ErrorKind::Timeout));
+    }
+    Ok(deadline - now)
+}
+
+/// TCP stream which fails reads and writes with a timeout error once the
+/// deadline has passed. Socket timeouts are set before every read and write so
+/// a peer which sends or receives slowly can't extend the deadline.
+pub struct DeadlineStream {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl DeadlineStream {
+    pub fn new(stream: TcpStream, deadline: Instant) -> Self {
+        Self { stream, deadline }
+    }
+
+    fn set_timeouts(&self) -> io::Result<()> {
+        let timeout =
+            remaining(self.deadline).map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?;
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.set_write_timeout(Some(timeout))
+    }
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.set_timeouts()?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for DeadlineStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.set_timeouts()?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Sets read and write timeouts of a stream so that they expire at the
+/// deadline.
+fn set_timeouts(stream: &TcpStream, deadline: Instant) -> Result<()> {
+    let timeout = remaining(deadline)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("127.0.0.1", None, Some("127.0.0.1"))]
+    #[case("127.0.0.1", Some(IpFamily::V4), Some("127.0.0.1"))]
+    #[case("127.0.0.1", Some(IpFamily::V6), None)]
+    #[case("[::1]", Some(IpFamily::V6), Some("::1"))]
+    #[case("[::1]", Some(IpFamily::V4), None)]
+    fn resolve_ip_address(
+        #[case] host: &str,
+        #[case] family: Option<IpFamily>,
+        #[case] expected: Option<&str>,
+    ) {
+        let host = Host::parse(host).unwrap();
+        match (resolve(&host, family), expected) {
+            (Ok(addr), Some(expected)) => assert_eq!(addr.to_string(), expected),
+            (Err(err), None) => assert_eq!(err.kind(), ErrorKind::DnsFailure),
+            (result, _) => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! SNTP client as defined in RFC 4330. A single request is sent to the server
+//! and the clock offset and round-trip delay are calculated from the
+//! timestamps of the request and the response.
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, ErrorKind, Result};
+
+const PACKET_LEN: usize = 48;
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// Response of an SNTP query.
+#[derive(Debug, PartialEq)]
+pub struct NtpResponse {
+    /// Offset of the server clock from the local clock in seconds. Positive
+    /// offset means that the local clock is behind.
+    pub offset: f64,
+    /// Round-trip delay in seconds without the processing time of the server.
+    pub delay: f64,
+    pub stratum: u8,
+}
+
+pub fn query(server: SocketAddr, timeout: Duration) -> Result<NtpResponse> {
+    let bind_addr: SocketAddr = match server {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.connect(server)?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    let transmit = timestamp(SystemTime::now());
+    socket.send(&request(transmit))?;
+    let mut buf = [0; PACKET_LEN];
+    loop {
+        let len = socket.recv(&mut buf)?;
+        let received = timestamp(SystemTime::now());
+        // Responses to earlier requests don't echo the transmit timestamp
+        if len == PACKET_LEN && buf[24..32] == transmit.to_be_bytes() {
+            return parse_response(&buf, transmit, received);
+        }
+    }
+}
+
+fn request(transmit: u64) -> [u8; PACKET_LEN] {
+    let mut packet = [0; PACKET_LEN];
+    packet[0] = VERSION << 3 | MODE_CLIENT;
+    packet[40..48].copy_from_slice(&transmit.to_be_bytes());
+    packet
+}
+
+/// Calculates the offset and delay from the client transmit time, server
+/// receive time, server transmit time and client receive time.
+fn parse_response(packet: &[u8; PACKET_LEN], sent: u64, received: u64) -> Result<NtpResponse> {
+    let leap = packet[0] >> 6;
+    let mode = packet[0] & 0x07;
+    let stratum = packet[1];
+    if mode != MODE_SERVER {
+        return Err(invalid_response("Unexpected NTP mode"));
+    }
+    // Stratum 0 is a kiss-o'-death message which tells the client to back off
+    if stratum == 0 || leap == LEAP_UNSYNCHRONIZED {
+        return Err(invalid_response("NTP server is not synchronized"));
+    }
+
+    let read = |offset: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&packet[offset..offset + 8]);
+        seconds(u64::from_be_bytes(bytes))
+    };
+    let (t1, t2, t3, t4) = (seconds(sent), read(32), read(40), seconds(received));
+    Ok(NtpResponse {
+        offset: ((t2 - t1) + (t3 - t4)) / 2.0,
+        delay: (t4 - t1) - (t3 - t2),
+        stratum,
+    })
+}
+
+fn invalid_response(message: &str) -> Error {
+    Error::new(message).set_kind(ErrorKind::Parse)
+}
+
+/// Converts a time to an NTP timestamp which has seconds since the NTP epoch
+/// in the upper 32 bits and the fraction of a second in the lower 32 bits.
+fn timestamp(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = (u64::from(since_epoch.subsec_nanos()) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+/// Converts an NTP timestamp to seconds since the NTP epoch.
+fn seconds(timestamp: u64) -> f64 {
+    (timestamp >> 32) as f64 + (timestamp & 0xffff_ffff) as f64 / 4_294_967_296.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn response(leap: u8, stratum: u8, receive: u64, transmit: u64) -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        packet[0] = leap << 6 | VERSION << 3 | MODE_SERVER;
+        packet[1] = stratum;
+        packet[32..40].copy_from_slice(&receive.to_be_bytes());
+        packet[40..48].copy_from_slice(&transmit.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn timestamp_of_unix_epoch() {
+        let time = UNIX_EPOCH + Duration::from_millis(500);
+
+        assert_eq!(timestamp(time), NTP_UNIX_OFFSET << 32 | 1 << 31);
+        assert_eq!(seconds(timestamp(time)), NTP_UNIX_OFFSET as f64 + 0.5);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn offset_and_delay() {
+        let at = |secs: u64| timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        // Server clock is 10 seconds ahead and both directions take a second
+        let packet = response(0, 2, at(111), at(112));
+        let response = parse_response(&packet, at(100), at(103)).unwrap();
+
+        assert_eq!(response.offset, 10.0);
+        assert_eq!(response.delay, 2.0);
+        assert_eq!(response.stratum, 2);
+    }
+
+    #[test]
+    fn unsynchronized_server() {
+        let err = parse_response(&response(0, 0, 0, 0), 0, 0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(parse_response(&response(LEAP_UNSYNCHRONIZED, 2, 0, 0), 0, 0).is_err());
+    }
+
+    #[test]
+    fn query_server() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut request = [0; PACKET_LEN];
+            let (_, client) = server.recv_from(&mut request).unwrap();
+            let now = timestamp(SystemTime::now());
+            let mut packet = response(0, 1, now, now);
+            packet[24..32].copy_from_slice(&request[40..48]);
+            server.send_to(&packet, client).unwrap();
+        });
+
+        let response = query(addr, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(response.stratum, 1);
+        assert!(response.offset.abs() < 1.0);
+        assert!(response.delay >= 0.0);
+    }
+}
@@ -0,0 +1,236 @@
+//! Default gateway lookup from the routing tables in `/proc/net/route` and
+//! `/proc/net/ipv6_route`. Tables are read at every lookup so that gateway
+//! changes are followed.
+//!
+//! Link-local IPv6 gateways are reached through their interface so they get
+//! the index of the interface from `/sys/class/net` as the scope id.
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use super::IpFamily;
+use crate::error::{Error, ErrorKind, Result};
+
+const PROC_NET_ROUTE: &str = "/proc/net/route";
+const PROC_NET_IPV6_ROUTE: &str = "/proc/net/ipv6_route";
+const SYS_CLASS_NET: &str = "/sys/class/net";
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+
+/// Default gateway and the interface which it's reached through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gateway {
+    pub addr: IpAddr,
+    pub interface: String,
+    /// Scope id of a link-local IPv6 gateway and 0 for other gateways.
+    pub scope_id: u32,
+}
+
+impl Gateway {
+    /// Returns the socket address of the gateway with its scope.
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.addr {
+            IpAddr::V4(addr) => SocketAddr::new(IpAddr::V4(addr), port),
+            IpAddr::V6(addr) => SocketAddrV6::new(addr, port, 0, self.scope_id).into(),
+        }
+    }
+}
+
+/// Default route from the routing table.
+#[derive(Debug, PartialEq, Eq)]
+struct Route {
+    gateway: Gateway,
+    metric: u32,
+}
+
+/// Returns the default gateway of the given family. IPv4 gateway is preferred
+/// when the family is not set. Route with the lowest metric is used when
+/// there are many default routes.
+pub fn default_gateway(family: Option<IpFamily>) -> Result<Gateway> {
+    let families = match family {
+        Some(family) => vec![family],
+        None => vec![IpFamily::V4, IpFamily::V6],
+    };
+    for family in families {
+        let (path, parse): (_, fn(&str) -> Vec<Route>) = match family {
+            IpFamily::V4 => (PROC_NET_ROUTE, parse_ipv4_routes),
+            IpFamily::V6 => (PROC_NET_IPV6_ROUTE, parse_ipv6_routes),
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            // IPv6 routing table doesn't exist when IPv6 is disabled
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(Error::new("Failed to read routing table")
+                    .set_cause(err)
+                    .set_context(path))
+            }
+        };
+        let route = parse(&contents)
+            .into_iter()
+            .min_by_key(|route| route.metric);
+        if let Some(mut route) = route {
+            if matches!(route.gateway.addr, IpAddr::V6(addr) if addr.is_unicast_link_local()) {
+                route.gateway.scope_id = interface_index(&route.gateway.interface)?;
+            }
+            return Ok(route.gateway);
+        }
+    }
+
+    let message = match family {
+        Some(IpFamily::V4) => "No IPv4 default gateway",
+        Some(IpFamily::V6) => "No IPv6 default gateway",
+        None => "No default gateway",
+    };
+    Err(Error::new(message).set_kind(ErrorKind::Unreachable))
+}
+
+fn interface_index(interface: &str) -> Result<u32> {
+    let path = format!("{}/{}/ifindex", SYS_CLASS_NET, interface);
+    fs::read_to_string(&path)
+        .map_err(|e| {
+            Error::new("Failed to read interface index")
+                .set_cause(e)
+                .set_context(&path)
+        })?
+        .trim()
+        .parse()
+        .map_err(|e| {
+            Error::new("Invalid interface index")
+                .set_cause(e)
+                .set_context(&path)
+        })
+}
+
+/// Parses default routes from `/proc/net/route` contents. Addresses are
+/// written as hexadecimal numbers in the byte order of the host.
+fn parse_ipv4_routes(contents: &str) -> Vec<Route> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let hex = |index: usize| u32::from_str_radix(fields.get(index)?, 16).ok();
+            let (destination, gateway, flags, metric, mask) = (
+                hex(1)?,
+                hex(2)?,
+                hex(3)?,
+                fields.get(6)?.parse().ok()?,
+                hex(7)?,
+            );
+            let is_default = destination == 0 && mask == 0 && has_gateway(flags);
+            is_default.then(|| Route {
+                gateway: Gateway {
+                    addr: IpAddr::V4(Ipv4Addr::from(gateway.to_ne_bytes())),
+                    interface: fields[0].to_string(),
+                    scope_id: 0,
+                },
+                metric,
+            })
+        })
+        .collect()
+}
+
+/// Parses default routes from `/proc/net/ipv6_route` contents. Fields are
+/// destination, prefix length, source, prefix length, next hop, metric,
+/// reference count, use count, flags and interface.
+fn parse_ipv6_routes(contents: &str) -> Vec<Route> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (destination, prefix_len, next_hop, metric, flags, interface) = (
+                parse_ipv6(fields.first()?)?,
+                u8::from_str_radix(fields.get(1)?, 16).ok()?,
+                parse_ipv6(fields.get(4)?)?,
+                u32::from_str_radix(fields.get(5)?, 16).ok()?,
+                u32::from_str_radix(fields.get(8)?, 16).ok()?,
+                fields.get(9)?,
+            );
+            let is_default = destination.is_unspecified() && prefix_len == 0 && has_gateway(flags);
+            is_default.then(|| Route {
+                gateway: Gateway {
+                    addr: IpAddr::V6(next_hop),
+                    interface: interface.to_string(),
+                    scope_id: 0,
+                },
+                metric,
+            })
+        })
+        .collect()
+}
+
+fn has_gateway(flags: u32) -> bool {
+    flags & (RTF_UP | RTF_GATEWAY) == RTF_UP | RTF_GATEWAY
+}
+
+/// Parses an IPv6 address which is written as 32 hexadecimal digits.
+fn parse_ipv6(hex: &str) -> Option<Ipv6Addr> {
+    u128::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 32)
+        .map(Ipv6Addr::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(addr: &str, interface: &str, metric: u32) -> Route {
+        Route {
+            gateway: Gateway {
+                addr: addr.parse().unwrap(),
+                interface: interface.to_string(),
+                scope_id: 0,
+            },
+            metric,
+        }
+    }
+
+    #[test]
+    fn parse_ipv4_default_routes() {
+        let gateway = u32::from_ne_bytes([192, 0, 2, 1]);
+        let network = u32::from_ne_bytes([192, 0, 2, 0]);
+        let mask = u32::from_ne_bytes([255, 255, 255, 0]);
+        let contents = format!(
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+             eth0\t00000000\t{:08X}\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+             eth0\t{:08X}\t00000000\t0001\t0\t0\t0\t{:08X}\t0\t0\t0\n\
+             wlan0\t00000000\t{:08X}\t0001\t0\t0\t50\t00000000\t0\t0\t0\n",
+            gateway, network, mask, gateway
+        );
+
+        assert_eq!(
+            parse_ipv4_routes(&contents),
+            vec![route("192.0.2.1", "eth0", 100)]
+        );
+    }
+
+    #[test]
+    fn parse_ipv6_default_routes() {
+        let contents = "\
+fd000000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 20010db8000000000000000000000001 00000400 00000002 00000000 00000003     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000100 00000002 00000000 00000003    wlan0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+";
+
+        assert_eq!(
+            parse_ipv6_routes(contents),
+            vec![
+                route("2001:db8::1", "eth0", 1024),
+                route("fe80::1", "wlan0", 256)
+            ]
+        );
+    }
+
+    #[test]
+    fn link_local_gateway_has_scope() {
+        let gateway = Gateway {
+            addr: "fe80::1".parse().unwrap(),
+            interface: "eth0".to_string(),
+            scope_id: 2,
+        };
+
+        assert_eq!(gateway.socket_addr(80).to_string(), "[fe80::1%2]:80");
+    }
+}
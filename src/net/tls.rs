@@ -0,0 +1,416 @@
+//! TLS client handshake which reports details about the negotiated session and
+//! the server certificate. Certificates are verified against the Mozilla root
+//! certificates or against a custom CA bundle.
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::{error, fmt};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    StreamOwned,
+};
+use url::Host as UrlHost;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::error::{Error, Result};
+use crate::url::Host;
+
+pub type TlsStream<S = TcpStream> = StreamOwned<ClientConnection, S>;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TlsOptions<'a> {
+    /// Accept certificates which fail verification.
+    pub skip_verify: bool,
+    /// PEM file with the CA certificates which are trusted instead of the
+    /// Mozilla root certificates.
+    pub ca_file: Option<&'a Path>,
+    /// Application protocols which are offered with ALPN, e.g. `http/1.1`.
+    pub alpn_protocols: &'a [&'a [u8]],
+}
+
+/// Details of an established TLS session.
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    /// Whether the server certificate passed verification.
+    pub cert_valid: bool,
+    pub certificate: Option<CertificateInfo>,
+    /// Negotiated protocol version, e.g. `TLSv1.3`.
+    pub version: String,
+    /// Negotiated cipher suite, e.g. `TLS13_AES_256_GCM_SHA384`.
+    pub cipher: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    pub issuer: String,
+    pub subject: String,
+    /// Expiry time of the certificate as a Unix timestamp.
+    pub not_after: i64,
+}
+
+/// Cause of a handshake which failed because the server certificate did not
+/// pass verification. The certificate is kept so that it can be reported.
+#[derive(Debug)]
+pub struct CertificateError {
+    pub certificate: CertificateInfo,
+    cause: io::Error,
+}
+
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.cause.fmt(f)
+    }
+}
+
+impl error::Error for CertificateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.cause)
+    }
+}
+
+/// Performs a TLS handshake on a connected stream. Handshakes which fail
+/// certificate verification have `CertificateError` as the cause.
+pub fn handshake<S: Read + Write>(
+    host: &Host,
+    mut stream: S,
+    options: TlsOptions,
+) -> Result<(TlsStream<S>, TlsInfo)> {
+    let server_name = match host {
+        Host::Host(UrlHost::Domain(domain)) => ServerName::try_from(domain.clone())
+            .map_err(|e| Error::new("Invalid TLS server name").set_cause(e))?,
+        Host::Host(UrlHost::Ipv4(addr)) => ServerName::from(*addr),
+        Host::Host(UrlHost::Ipv6(addr)) => ServerName::from(*addr),
+        Host::Gateway => {
+            return Err(Error::new("Invalid TLS server name").set_context(&host.to_string()))
+        }
+    };
+
+    let verifier = Arc::new(RecordingVerifier::new(options)?);
+    let mut config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Error::new("Failed to configure TLS").set_cause(e))?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+    config.alpn_protocols = options
+        .alpn_protocols
+        .iter()
+        .map(|protocol| protocol.to_vec())
+        .collect();
+
+    let mut connection = ClientConnection::new(Arc::new(config), server_name)
+        .map_err(|e| Error::new("Failed to create TLS connection").set_cause(e))?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream).map_err(|e| {
+            let err = Error::new("TLS handshake failed").set_context(&host.to_string());
+            let certificate = verifier.certificate.lock().unwrap().take();
+            match certificate {
+                Some(certificate) if !verifier.valid.load(Ordering::Relaxed) => {
+                    err.set_cause(CertificateError {
+                        certificate,
+                        cause: e,
+                    })
+                }
+                _ => err.set_cause(e),
+            }
+        })?;
+    }
+
+    let info = TlsInfo {
+        cert_valid: verifier.valid.load(Ordering::Relaxed),
+        certificate: verifier.certificate.lock().unwrap().take(),
+        version: connection
+            .protocol_version()
+            .and_then(|version| version.as_str())
+            .map_or_else(
+                || "unknown".to_string(),
+                |version| version.replace('_', "."),
+            ),
+        cipher: connection
+            .negotiated_cipher_suite()
+            .and_then(|suite| suite.suite().as_str())
+            .unwrap_or("unknown")
+            .to_string(),
+    };
+    Ok((StreamOwned::new(connection, stream), info))
+}
+
+fn parse_certificate(der: &CertificateDer) -> Option<CertificateInfo> {
+    let (_, cert) = X509Certificate::from_der(der.as_ref()).ok()?;
+    Some(CertificateInfo {
+        issuer: cert.issuer().to_string(),
+        subject: cert.subject().to_string(),
+        not_after: cert.validity().not_after.timestamp(),
+    })
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    static PROVIDER: OnceLock<Arc<CryptoProvider>> = OnceLock::new();
+    PROVIDER
+        .get_or_init(|| Arc::new(crypto::ring::default_provider()))
+        .clone()
+}
+
+fn root_certificates(ca_file: Option<&Path>) -> Result<Arc<RootCertStore>> {
+    static MOZILLA_ROOTS: OnceLock<Arc<RootCertStore>> = OnceLock::new();
+    let Some(ca_file) = ca_file else {
+        return Ok(MOZILLA_ROOTS
+            .get_or_init(|| {
+                Arc::new(RootCertStore::from_iter(
+                    webpki_roots::TLS_SERVER_ROOTS.iter().cloned(),
+                ))
+            })
+            .clone());
+    };
+
+    let context = ca_file.display().to_string();
+    let file = File::open(ca_file).map_err(|e| {
+        Error::new("Failed to open CA file")
+            .set_cause(e)
+            .set_context(&context)
+    })?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(file)) {
+        let cert = cert.map_err(|e| {
+            Error::new("Failed to read CA file")
+                .set_cause(e)
+                .set_context(&context)
+        })?;
+        roots.add(cert).map_err(|e| {
+            Error::new("Invalid CA certificate")
+                .set_cause(e)
+                .set_context(&context)
+        })?;
+    }
+    if roots.is_empty() {
+        return Err(Error::new("CA file has no certificates").set_context(&context));
+    }
+    Ok(Arc::new(roots))
+}
+
+/// Certificate verifier which records the server certificate and whether it
+/// was valid. Invalid certificates are accepted when verification is skipped.
+#[derive(Debug)]
+struct RecordingVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    skip_verify: bool,
+    valid: AtomicBool,
+    certificate: Mutex<Option<CertificateInfo>>,
+}
+
+impl RecordingVerifier {
+    fn new(options: TlsOptions) -> Result<Self> {
+        let inner = WebPkiServerVerifier::builder_with_provider(
+            root_certificates(options.ca_file)?,
+            provider(),
+        )
+        .build()
+        .map_err(|e| Error::new("Failed to configure TLS").set_cause(e))?;
+        Ok(Self {
+            inner,
+            skip_verify: options.skip_verify,
+            valid: AtomicBool::new(false),
+            certificate: Mutex::new(None),
+        })
+    }
+}
+
+impl ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        *self.certificate.lock().unwrap() = parse_certificate(end_entity);
+        match self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        ) {
+            Ok(verified) => {
+                self.valid.store(true, Ordering::Relaxed);
+                Ok(verified)
+            }
+            Err(_) if self.skip_verify => Ok(ServerCertVerified::assertion()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    //! Test TLS server with certificates signed by a generated CA.
+    use std::io::{BufRead, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use rstest::*;
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::{ServerConfig, ServerConnection};
+
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::test_util::TempDir;
+
+    pub struct TestServer {
+        pub addr: SocketAddr,
+        /// CA certificate in PEM format.
+        pub ca: String,
+    }
+
+    /// Starts a server which accepts one connection and responds with
+    /// `200 OK` to the first request.
+    pub fn start_server() -> TestServer {
+        start_server_with_alpn(&[])
+    }
+
+    /// Starts a server which accepts only the given application protocols
+    /// if any are given.
+    fn start_server_with_alpn(protocols: &[&[u8]]) -> TestServer {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "Uption Test CA");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "uption.test");
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+        let mut config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+            )
+            .unwrap();
+        config.alpn_protocols = protocols.iter().map(|protocol| protocol.to_vec()).collect();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut stream = BufReader::new(StreamOwned::new(connection, tcp));
+            let mut line = String::new();
+            while stream.read_line(&mut line).is_ok_and(|n| n > 0) && line != "\r\n" {
+                line.clear();
+            }
+            let _ = stream
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        });
+
+        TestServer { addr, ca: ca.pem() }
+    }
+
+    fn connect(server: &TestServer, options: TlsOptions) -> Result<TlsInfo> {
+        let host = Host::parse(&server.addr.ip().to_string()).unwrap();
+        let tcp = TcpStream::connect(server.addr).unwrap();
+        handshake(&host, tcp, options).map(|(_, info)| info)
+    }
+
+    #[test]
+    fn untrusted_certificate_is_rejected() {
+        let server = start_server();
+        let err = connect(&server, TlsOptions::default()).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::Tls);
+        let cause = err.cause().as_ref().unwrap();
+        let cert = &cause
+            .downcast_ref::<CertificateError>()
+            .unwrap()
+            .certificate;
+        assert_eq!(cert.subject, "CN=uption.test");
+    }
+
+    #[test]
+    fn untrusted_certificate_is_accepted_when_verification_is_skipped() {
+        let server = start_server();
+        let options = TlsOptions {
+            skip_verify: true,
+            ..Default::default()
+        };
+        let info = connect(&server, options).unwrap();
+
+        assert!(!info.cert_valid);
+        let cert = info.certificate.unwrap();
+        assert_eq!(cert.subject, "CN=uption.test");
+        assert_eq!(cert.issuer, "CN=Uption Test CA");
+    }
+
+    #[test]
+    fn certificate_is_verified_with_ca_file() {
+        let server = start_server();
+        let dir = TempDir::new("ca");
+        let ca_file = dir.join("ca.pem");
+        std::fs::write(&ca_file, &server.ca).unwrap();
+        let options = TlsOptions {
+            ca_file: Some(&ca_file),
+            ..Default::default()
+        };
+        let info = connect(&server, options).unwrap();
+
+        assert!(info.cert_valid);
+        assert!(info.version.starts_with("TLSv1."));
+        assert!(info.cipher.starts_with("TLS"));
+        assert!(info.certificate.unwrap().not_after > chrono::Utc::now().timestamp());
+    }
+
+    #[rstest]
+    #[case(b"dot", true)]
+    #[case(b"http/1.1", false)]
+    fn alpn_protocol_is_offered(#[case] protocol: &[u8], #[case] accepted: bool) {
+        let server = start_server_with_alpn(&[b"dot"]);
+        let options = TlsOptions {
+            skip_verify: true,
+            alpn_protocols: &[protocol],
+            ..Default::default()
+        };
+
+        assert_eq!(connect(&server, options).is_ok(), accepted);
+    }
+}
@@ -0,0 +1,356 @@
+//! Hop limited probes for tracing the network path to a host. Probes are UDP
+//! datagrams or ICMP echo requests. Replies from the routers on the path and
+//! from the destination are received with a raw ICMP socket which requires
+//! `CAP_NET_RAW`.
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use super::icmp::{self, ICMPV4_ECHO_REPLY, ICMPV6_ECHO_REPLY, ICMP_HEADER_LEN};
+use crate::error::{Error, Result};
+
+/// Destination port of the first UDP probe. Every probe uses the next port so
+/// that the replies can be matched to the probes.
+const BASE_PORT: u16 = 33434;
+const UDP_PAYLOAD: &[u8; 32] = b"uption-traceroute-probe-payload!";
+const ICMPV4_UNREACHABLE: u8 = 3;
+const ICMPV4_PORT_UNREACHABLE: u8 = 3;
+const ICMPV4_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_UNREACHABLE: u8 = 1;
+const ICMPV6_PORT_UNREACHABLE: u8 = 4;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+const IPV6_HEADER_LEN: usize = 40;
+const PROTOCOL_ICMPV4: u8 = 1;
+const PROTOCOL_UDP: u8 = 17;
+const PROTOCOL_ICMPV6: u8 = 58;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeProtocol {
+    Udp,
+    Icmp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyKind {
+    /// Router on the path reported that the hop limit was exceeded.
+    TimeExceeded,
+    /// Destination replied to the probe.
+    Destination,
+    /// Router or the destination reported that the destination is
+    /// unreachable.
+    Unreachable,
+}
+
+#[derive(Debug)]
+pub struct ProbeReply {
+    pub from: IpAddr,
+    pub kind: ReplyKind,
+    pub rtt: Duration,
+}
+
+pub struct Tracer {
+    addr: IpAddr,
+    /// Scope id of a link-local IPv6 destination.
+    scope_id: u32,
+    icmp: Socket,
+    /// Socket for sending UDP probes. ICMP probes are sent with the ICMP
+    /// socket.
+    udp: Option<Socket>,
+    identifier: u16,
+    local_port: u16,
+}
+
+impl Tracer {
+    /// Creates a tracer for the destination. Port of the address is ignored.
+    pub fn new(target: SocketAddr, protocol: ProbeProtocol) -> Result<Self> {
+        let (addr, scope_id) = match target {
+            SocketAddr::V4(target) => (IpAddr::V4(*target.ip()), 0),
+            SocketAddr::V6(target) => (IpAddr::V6(*target.ip()), target.scope_id()),
+        };
+        let (domain, icmp_protocol, unspecified) = match addr {
+            IpAddr::V4(_) => (
+                Domain::IPV4,
+                Protocol::ICMPV4,
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            ),
+            IpAddr::V6(_) => (
+                Domain::IPV6,
+                Protocol::ICMPV6,
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ),
+        };
+        let icmp = Socket::new(domain, Type::RAW, Some(icmp_protocol)).map_err(|e| {
+            Error::new("Failed to open ICMP socket")
+                .set_cause(e)
+                .set_context("traceroute requires CAP_NET_RAW")
+        })?;
+
+        let (udp, local_port) = match protocol {
+            ProbeProtocol::Udp => {
+                let udp = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+                udp.bind(&SocketAddr::new(unspecified, 0).into())?;
+                let local_port = udp
+                    .local_addr()?
+                    .as_socket()
+                    .map_or(0, |local| local.port());
+                (Some(udp), local_port)
+            }
+            ProbeProtocol::Icmp => (None, 0),
+        };
+
+        Ok(Self {
+            addr,
+            scope_id,
+            icmp,
+            udp,
+            identifier: rand::random(),
+            local_port,
+        })
+    }
+
+    /// Sends `count` probes with the given hop limit at once and waits for
+    /// the replies until the timeout. Probes are numbered from
+    /// `first_sequence` so that the replies can be matched to them.
+    pub fn probe(
+        &self,
+        hop_limit: u8,
+        first_sequence: u16,
+        count: u16,
+        timeout: Duration,
+    ) -> Result<Vec<ProbeReply>> {
+        let socket = self.udp.as_ref().unwrap_or(&self.icmp);
+        set_hop_limit(socket, self.addr, hop_limit)?;
+        let mut pending = HashMap::new();
+        for offset in 0..count {
+            let sequence = first_sequence.wrapping_add(offset);
+            pending.insert(sequence, Instant::now());
+            match self.udp.as_ref() {
+                Some(udp) => {
+                    let port = BASE_PORT.wrapping_add(sequence);
+                    udp.send_to(UDP_PAYLOAD, &self.target(port).into())?;
+                }
+                None => {
+                    let request = icmp::echo_request(self.addr, self.identifier, sequence);
+                    self.icmp.send_to(&request, &self.target(0).into())?;
+                }
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut replies = Vec::new();
+        let mut buf = [0; 1500];
+        while !pending.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            self.icmp.set_read_timeout(Some(deadline - now))?;
+
+            let (len, from) = match self.recv(&mut buf) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let Some(from) = from.as_socket().map(|from| from.ip()) else {
+                continue;
+            };
+            let Some((sequence, kind)) = self.parse_reply(&buf[..len]) else {
+                continue;
+            };
+            if let Some(sent) = pending.remove(&sequence) {
+                replies.push(ProbeReply {
+                    from,
+                    kind,
+                    rtt: sent.elapsed(),
+                });
+            }
+        }
+        Ok(replies)
+    }
+
+    fn target(&self, port: u16) -> SocketAddr {
+        match self.addr {
+            IpAddr::V4(addr) => SocketAddr::new(IpAddr::V4(addr), port),
+            IpAddr::V6(addr) => SocketAddrV6::new(addr, port, 0, self.scope_id).into(),
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> std::io::Result<(usize, SockAddr)> {
+        let from = self.icmp.peek_sender()?;
+        let len = (&self.icmp).read(buf)?;
+        Ok((len, from))
+    }
+
+    /// Returns the sequence number of the probe and the kind of the reply if
+    /// the ICMP packet is a reply to a probe of this tracer.
+    fn parse_reply(&self, packet: &[u8]) -> Option<(u16, ReplyKind)> {
+        // Raw IPv4 sockets receive the IP header in front of the ICMP message.
+        let packet = match self.addr {
+            IpAddr::V4(_) => packet.get(usize::from(packet.first()? & 0x0f) * 4..)?,
+            IpAddr::V6(_) => packet,
+        };
+        if packet.len() < ICMP_HEADER_LEN {
+            return None;
+        }
+
+        let udp = self.udp.is_some();
+        let kind = match (self.addr, packet[0], packet[1]) {
+            (IpAddr::V4(_), ICMPV4_ECHO_REPLY, _) | (IpAddr::V6(_), ICMPV6_ECHO_REPLY, _) => {
+                let sequence = (!udp)
+                    .then(|| self.echo_sequence(&packet[..ICMP_HEADER_LEN]))
+                    .flatten()?;
+                return Some((sequence, ReplyKind::Destination));
+            }
+            (IpAddr::V4(_), ICMPV4_TIME_EXCEEDED, _) | (IpAddr::V6(_), ICMPV6_TIME_EXCEEDED, _) => {
+                ReplyKind::TimeExceeded
+            }
+            (IpAddr::V4(_), ICMPV4_UNREACHABLE, ICMPV4_PORT_UNREACHABLE)
+            | (IpAddr::V6(_), ICMPV6_UNREACHABLE, ICMPV6_PORT_UNREACHABLE)
+                if udp =>
+            {
+                ReplyKind::Destination
+            }
+            (IpAddr::V4(_), ICMPV4_UNREACHABLE, _) | (IpAddr::V6(_), ICMPV6_UNREACHABLE, _) => {
+                ReplyKind::Unreachable
+            }
+            _ => return None,
+        };
+
+        // ICMP errors quote the IP header and the first bytes of the probe.
+        let quoted = &packet[ICMP_HEADER_LEN..];
+        let (protocol, probe) = match self.addr {
+            IpAddr::V4(_) => (
+                *quoted.get(9)?,
+                quoted.get(usize::from(quoted.first()? & 0x0f) * 4..)?,
+            ),
+            IpAddr::V6(_) => (*quoted.get(6)?, quoted.get(IPV6_HEADER_LEN..)?),
+        };
+        if probe.len() < ICMP_HEADER_LEN {
+            return None;
+        }
+        let sequence = if udp {
+            let is_probe = protocol == PROTOCOL_UDP && probe[0..2] == self.local_port.to_be_bytes();
+            let port = u16::from_be_bytes([probe[2], probe[3]]);
+            is_probe.then(|| port.wrapping_sub(BASE_PORT))?
+        } else if matches!(protocol, PROTOCOL_ICMPV4 | PROTOCOL_ICMPV6) {
+            self.echo_sequence(probe)?
+        } else {
+            return None;
+        };
+        Some((sequence, kind))
+    }
+
+    /// Returns the sequence number of an ICMP echo message which has the
+    /// identifier of this tracer.
+    fn echo_sequence(&self, header: &[u8]) -> Option<u16> {
+        (header[4..6] == self.identifier.to_be_bytes())
+            .then(|| u16::from_be_bytes([header[6], header[7]]))
+    }
+}
+
+fn set_hop_limit(socket: &Socket, addr: IpAddr, hop_limit: u8) -> Result<()> {
+    match addr {
+        IpAddr::V4(_) => socket.set_ttl(hop_limit.into())?,
+        IpAddr::V6(_) => socket.set_unicast_hops_v6(hop_limit.into())?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    fn tracer(udp: bool) -> Tracer {
+        Tracer {
+            addr: "192.0.2.1".parse().unwrap(),
+            scope_id: 0,
+            icmp: Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap(),
+            udp: udp.then(|| Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap()),
+            identifier: 0x1234,
+            local_port: 40000,
+        }
+    }
+
+    /// Builds an IPv4 packet with an ICMP error which quotes a probe.
+    fn icmp_error(icmp_type: u8, code: u8, protocol: u8, probe: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45];
+        packet.extend_from_slice(&[0; 19]);
+        packet.extend_from_slice(&[icmp_type, code, 0, 0, 0, 0, 0, 0]);
+        let mut quoted = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 1, protocol];
+        quoted.extend_from_slice(&[0; 10]);
+        packet.extend_from_slice(&quoted);
+        packet.extend_from_slice(probe);
+        packet
+    }
+
+    fn udp_probe(sequence: u16) -> Vec<u8> {
+        let mut probe = 40000u16.to_be_bytes().to_vec();
+        probe.extend_from_slice(&(BASE_PORT + sequence).to_be_bytes());
+        probe.extend_from_slice(&[0; 4]);
+        probe
+    }
+
+    #[rstest]
+    #[case(ICMPV4_TIME_EXCEEDED, 0, Some((7, ReplyKind::TimeExceeded)))]
+    #[case(
+        ICMPV4_UNREACHABLE,
+        ICMPV4_PORT_UNREACHABLE,
+        Some((7, ReplyKind::Destination))
+    )]
+    #[case(ICMPV4_UNREACHABLE, 1, Some((7, ReplyKind::Unreachable)))]
+    #[case(5, 0, None)]
+    fn reply_to_udp_probe(
+        #[case] icmp_type: u8,
+        #[case] code: u8,
+        #[case] expected: Option<(u16, ReplyKind)>,
+    ) {
+        let packet = icmp_error(icmp_type, code, PROTOCOL_UDP, &udp_probe(7));
+
+        assert_eq!(tracer(true).parse_reply(&packet), expected);
+    }
+
+    #[test]
+    fn reply_to_udp_probe_of_other_tracer() {
+        let mut probe = udp_probe(7);
+        probe[0..2].copy_from_slice(&40001u16.to_be_bytes());
+        let packet = icmp_error(ICMPV4_TIME_EXCEEDED, 0, PROTOCOL_UDP, &probe);
+
+        assert_eq!(tracer(true).parse_reply(&packet), None);
+    }
+
+    #[test]
+    fn reply_to_icmp_probe() {
+        let tracer = tracer(false);
+        let request = icmp::echo_request(tracer.addr, 0x1234, 7);
+        let packet = icmp_error(ICMPV4_TIME_EXCEEDED, 0, PROTOCOL_ICMPV4, &request);
+
+        assert_eq!(
+            tracer.parse_reply(&packet),
+            Some((7, ReplyKind::TimeExceeded))
+        );
+        let request = icmp::echo_request(tracer.addr, 0x4321, 7);
+        let packet = icmp_error(ICMPV4_TIME_EXCEEDED, 0, PROTOCOL_ICMPV4, &request);
+        assert_eq!(tracer.parse_reply(&packet), None);
+    }
+
+    #[test]
+    fn echo_reply_from_destination() {
+        let tracer = tracer(false);
+        let mut packet = vec![0x45];
+        packet.extend_from_slice(&[0; 19]);
+        packet.extend_from_slice(&[ICMPV4_ECHO_REPLY, 0, 0, 0, 0x12, 0x34, 0, 7]);
+
+        assert_eq!(
+            tracer.parse_reply(&packet),
+            Some((7, ReplyKind::Destination))
+        );
+        packet[24] = 0x99;
+        assert_eq!(tracer.parse_reply(&packet), None);
+    }
+}
@@ -2,10 +2,11 @@
 use std::thread;
 
 use crossbeam_channel::{Receiver, Sender};
-use log::info;
+use log::{error, info};
 
 use crate::collectors::CollectorScheduler;
 use crate::config::{Configure, UptionConfig};
+use crate::error::{Result, ResultError};
 use crate::exporters::ExporterScheduler;
 use crate::logging::Logger;
 use crate::message::Message;
@@ -21,35 +22,77 @@ impl Uption {
         Uption { config }
     }
 
-    pub fn start(&self) {
+    pub fn start(&self) -> Result<()> {
         Logger::from_config(&self.config).start();
         info!("Uption v{} started", UPTION_VERSION.unwrap_or("-unknown"));
 
-        let (sender, receiver) = crossbeam_channel::unbounded();
-        let collector_scheduler = self.start_collector_scheduler(sender);
-        let exporter_scheduler = self.start_exporter_scheduler(receiver);
+        let schedulers = ExporterScheduler::all_from_config(&self.config).set_source("uption")?;
+        let mut senders = Vec::new();
+        let mut exporter_schedulers = Vec::new();
+        for (index, scheduler) in schedulers.into_iter().enumerate() {
+            let (sender, receiver) = crossbeam_channel::unbounded();
+            senders.push(sender);
+            exporter_schedulers.push(self.start_exporter_scheduler(index, scheduler, receiver));
+        }
+        let collector_scheduler = self.start_collector_scheduler(senders);
 
         collector_scheduler
             .join()
             .expect("The collector scheduler thread has panicked");
-        exporter_scheduler
-            .join()
-            .expect("The export scheduler thread has panicked");
+        for exporter_scheduler in exporter_schedulers {
+            exporter_scheduler
+                .join()
+                .expect("The export scheduler thread has panicked");
+        }
         info!("Uption stopped");
+        Ok(())
+    }
+
+    /// Runs every collector once and exports the collected messages. Returns
+    /// the number of failed collections and failed exporters.
+    pub fn run_once(&self) -> Result<(usize, usize)> {
+        Logger::from_config(&self.config).start();
+        info!(
+            "Uption v{} collecting once",
+            UPTION_VERSION.unwrap_or("-unknown")
+        );
+
+        let exporters = ExporterScheduler::all_without_spool(&self.config).set_source("uption")?;
+        let collectors = CollectorScheduler::from_config(&self.config);
+        let (messages, collection_failures) = collectors.run_once(&self.config.general.hostname);
+
+        let mut export_failures = 0;
+        for exporter in exporters.iter() {
+            if let Err(err) = exporter.export_once(&messages) {
+                error!("{}", err);
+                export_failures += 1;
+            }
+        }
+        info!(
+            "Collected {} messages with {} failed collections and {} failed exporters",
+            messages.len(),
+            collection_failures,
+            export_failures
+        );
+        Ok((collection_failures, export_failures))
     }
 
-    fn start_collector_scheduler(&self, sender: Sender<Message>) -> thread::JoinHandle<()> {
-        let scheduler = CollectorScheduler::from_config(&self.config);
+    fn start_collector_scheduler(&self, senders: Vec<Sender<Message>>) -> thread::JoinHandle<()> {
+        let mut scheduler = CollectorScheduler::from_config(&self.config);
         let builder = thread::Builder::new().name("collector_scheduler".into());
         let hostname = self.config.general.hostname.to_owned();
         builder
-            .spawn(move || scheduler.start(sender, hostname))
+            .spawn(move || scheduler.start(senders, hostname))
             .unwrap()
     }
 
-    fn start_exporter_scheduler(&self, receiver: Receiver<Message>) -> thread::JoinHandle<()> {
-        let mut scheduler = ExporterScheduler::from_config(&self.config);
-        let builder = thread::Builder::new().name("export_scheduler".into());
+    fn start_exporter_scheduler(
+        &self,
+        index: usize,
+        mut scheduler: ExporterScheduler,
+        receiver: Receiver<Message>,
+    ) -> thread::JoinHandle<()> {
+        let builder = thread::Builder::new().name(format!("export_scheduler_{}", index));
         builder.spawn(move || scheduler.start(receiver)).unwrap()
     }
 }
@@ -1,22 +1,41 @@
 use std::fmt;
+use std::net::IpAddr;
 use std::str;
 
 use serde::de::{self, Visitor};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use url::{Host as UrlHost, ParseError};
 
+/// Keyword which refers to the default gateway.
+const GATEWAY: &str = "@gateway";
+
 #[derive(Debug, Clone)]
 pub enum Host {
     Host(UrlHost),
+    /// Default gateway of the device. Address of the gateway is looked up
+    /// from the routing table when the host is resolved.
+    Gateway,
 }
 
 impl Host {
     pub fn parse(input: &str) -> Result<Host, ParseError> {
+        if input == GATEWAY {
+            return Ok(Host::Gateway);
+        }
         let host = UrlHost::parse(input)?;
         Ok(Host::Host(host))
     }
 }
 
+impl From<IpAddr> for Host {
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => Host::Host(UrlHost::Ipv4(addr)),
+            IpAddr::V6(addr) => Host::Host(UrlHost::Ipv6(addr)),
+        }
+    }
+}
+
 impl str::FromStr for Host {
     type Err = ParseError;
 
@@ -29,10 +48,20 @@ impl fmt::Display for Host {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Host::Host(host) => write!(f, "{}", host),
+            Host::Gateway => f.write_str(GATEWAY),
         }
     }
 }
 
+impl Serialize for Host {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 impl<'de> Deserialize<'de> for Host {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -44,19 +73,81 @@ impl<'de> Deserialize<'de> for Host {
             type Value = Host;
 
             fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                f.write_str("host or IP address as a string")
+                f.write_str("host, IP address or @gateway as a string")
             }
 
             fn visit_str<E>(self, host: &str) -> Result<Self::Value, E>
             where
                 E: de::Error,
             {
-                UrlHost::parse(host)
-                    .map(Host::Host)
-                    .map_err(de::Error::custom)
+                Host::parse(host).map_err(de::Error::custom)
             }
         }
 
         deserializer.deserialize_any(HostVisitor)
     }
 }
+
+/// Host and port, e.g. `example.com:22` or `[2001:db8::1]:5432`.
+#[derive(Debug, Clone)]
+pub struct HostPort {
+    pub host: Host,
+    pub port: u16,
+}
+
+impl str::FromStr for HostPort {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<HostPort, String> {
+        let (host, port) = input
+            .rsplit_once(':')
+            .ok_or_else(|| format!("missing port in {}", input))?;
+        Ok(HostPort {
+            host: Host::parse(host).map_err(|e| format!("invalid host in {}: {}", input, e))?,
+            port: port
+                .parse()
+                .map_err(|e| format!("invalid port in {}: {}", input, e))?,
+        })
+    }
+}
+
+impl fmt::Display for HostPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl Serialize for HostPort {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HostPort {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct HostPortVisitor;
+
+        impl<'de> Visitor<'de> for HostPortVisitor {
+            type Value = HostPort;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("host and port as a string")
+            }
+
+            fn visit_str<E>(self, input: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                input.parse().map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(HostPortVisitor)
+    }
+}
@@ -5,4 +5,4 @@ mod host;
 mod url;
 
 pub use self::url::HttpUrl;
-pub use host::Host;
+pub use host::{Host, HostPort};
@@ -2,9 +2,11 @@ use std::fmt;
 use std::str;
 
 use serde::de::{self, Visitor};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use url::form_urlencoded::Serializer;
-use url::{ParseError as UrlParseError, Url, UrlQuery};
+use url::{ParseError as UrlParseError, Position, Url, UrlQuery};
+
+use super::Host;
 
 #[derive(Debug, Clone)]
 pub struct HttpUrl {
@@ -49,7 +51,31 @@ impl HttpUrl {
         self.url.as_str()
     }
 
-    pub fn query_pairs_mut(&mut self) -> Serializer<UrlQuery> {
+    pub fn host(&self) -> Host {
+        // HTTP and HTTPS URLs always have a host
+        Host::Host(self.url.host().unwrap().to_owned())
+    }
+
+    /// Returns the port of the URL or the default port of the scheme.
+    pub fn port(&self) -> u16 {
+        self.url.port_or_known_default().unwrap()
+    }
+
+    pub fn is_https(&self) -> bool {
+        self.url.scheme() == "https"
+    }
+
+    /// Returns the host and port as they are sent in the `Host` header.
+    pub fn authority(&self) -> &str {
+        &self.url[Position::BeforeHost..Position::AfterPort]
+    }
+
+    /// Returns the path and query as they are sent in the request line.
+    pub fn request_target(&self) -> &str {
+        &self.url[Position::BeforePath..Position::AfterQuery]
+    }
+
+    pub fn query_pairs_mut(&mut self) -> Serializer<'_, UrlQuery<'_>> {
         self.url.query_pairs_mut()
     }
 
@@ -72,6 +98,23 @@ impl fmt::Display for HttpUrl {
     }
 }
 
+/// URLs are serialized with the password redacted.
+impl Serialize for HttpUrl {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if self.url.password().is_none() {
+            return serializer.collect_str(self);
+        }
+        serializer.collect_str(&format_args!(
+            "{}<redacted>{}",
+            &self.url[..Position::BeforePassword],
+            &self.url[Position::AfterPassword..]
+        ))
+    }
+}
+
 impl<'de> Deserialize<'de> for HttpUrl {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -2,6 +2,12 @@
 use assert_cmd::Command;
 use std::str;
 
+#[path = "../src/test_util.rs"]
+#[allow(dead_code)]
+mod test_util;
+
+use test_util::TempDir;
+
 #[test]
 fn test_uption_start_with_empty_config() {
     let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
@@ -20,3 +26,82 @@ fn test_uption_start_with_empty_config() {
         "Output did not contain text 'No collectors configured!'"
     );
 }
+
+fn write_config(dir: &TempDir, contents: &str) -> std::path::PathBuf {
+    let path = dir.join("uption.toml");
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn test_uption_default_config_passes_check() {
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .arg("print-default-config")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let dir = TempDir::new("default");
+    let path = write_config(&dir, str::from_utf8(&output.stdout).unwrap());
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["check", "--config"])
+        .arg(&path)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_uption_check_redacts_secrets() {
+    let dir = TempDir::new("check");
+    let path = write_config(
+        &dir,
+        r#"
+[exporters]
+exporter = "influxdb"
+
+[exporters.influxdb]
+url = "http://localhost:8086"
+bucket = "bucket"
+organization = "organization"
+token = "supersecret"
+"#,
+    );
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .arg("check")
+        .arg("--config")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+    assert!(stdout.contains("token = \"<redacted>\""));
+    assert!(!stdout.contains("supersecret"));
+}
+
+#[test]
+fn test_uption_once_exits_with_failure_status() {
+    let dir = TempDir::new("once");
+    let path = write_config(
+        &dir,
+        r#"
+[collectors.http]
+enabled = true
+urls = ["http://127.0.0.1:9/"]
+timeout = 1
+"#,
+    );
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["once", "--config"])
+        .arg(&path)
+        .timeout(std::time::Duration::from_secs(10))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(str::from_utf8(&output.stdout)
+        .unwrap()
+        .contains("error_class=\"connection_refused\""));
+}